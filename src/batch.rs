@@ -0,0 +1,181 @@
+//! Reassembly of [IRCv3 batches](https://ircv3.net/specs/extensions/batch).
+
+use std::collections::HashMap;
+
+use {Command, KnownCommand, Message, OwnedMessage};
+
+/// Maximum number of batches a `Tracker` keeps open at once.
+pub const MAX_OPEN_BATCHES: usize = 64;
+
+/// Complete batch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Batch {
+    /// Reference tag the batch was opened with.
+    pub reference: Vec<u8>,
+    /// Batch type, e.g. `netsplit` or `chathistory`.
+    pub batch_type: Vec<u8>,
+    /// Additional parameters of the batch type.
+    pub params: Vec<Vec<u8>>,
+    /// Tags of the `BATCH +reference` message, e.g. `label`.
+    pub tags: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    /// Messages and nested batches in the order they were received.
+    pub children: Vec<Item>,
+}
+
+/// Message or batch yielded by `Tracker`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Item {
+    /// Message that isn't part of a batch.
+    Message(OwnedMessage),
+    /// Batch that was closed.
+    Batch(Batch),
+}
+
+/// Groups messages by their `batch` tag.
+#[derive(Clone, Debug, Default)]
+pub struct Tracker {
+    /// Open batches and the references of their parents.
+    open: HashMap<Vec<u8>, (Option<Vec<u8>>, Batch)>,
+}
+
+impl Tracker {
+    /// Create a tracker without any open batches.
+    pub fn new() -> Tracker {
+        Tracker::default()
+    }
+
+    /// Whether a batch with the reference tag `reference` is open.
+    pub fn is_open(&self, reference: &[u8]) -> bool {
+        self.open.contains_key(reference)
+    }
+
+    /// Process a message. Returns the message if it isn't part of a batch, or a batch once it's
+    /// closed and isn't nested in another batch. Messages that refer to an unknown batch are
+    /// returned as is, including `BATCH -reference` for a batch that isn't open. Once
+    /// `MAX_OPEN_BATCHES` batches are open, further `BATCH +reference` messages aren't tracked
+    /// and are returned as is like the messages that refer to them.
+    pub fn handle(&mut self, message: &Message) -> Option<Item> {
+        let parent = message.tag(b"batch").filter(|parent| self.is_open(parent));
+
+        if message.command == Command::Command(KnownCommand::BATCH) && !message.params.is_empty() {
+            let param = message.params[0];
+            match param.first() {
+                Some(&b'+') if message.params.len() > 1 && self.open.len() < MAX_OPEN_BATCHES => {
+                    let batch = Batch {
+                        reference: param[1..].to_vec(),
+                        batch_type: message.params[1].to_vec(),
+                        params: message.params[2..].iter().map(|param| param.to_vec()).collect(),
+                        tags: message.tags.iter().map(|(k, v)| (k.to_vec(), v.as_ref().map(|v| v.to_vec()))).collect(),
+                        children: vec![],
+                    };
+                    self.open.insert(batch.reference.clone(), (parent.map(<[u8]>::to_vec), batch));
+                    return None;
+                }
+                Some(&b'-') => {
+                    if let Some((parent, batch)) = self.open.remove(&param[1..]) {
+                        return self.add(parent.as_ref().map(|parent| &parent[..]), Item::Batch(batch));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        self.add(parent, Item::Message(message.clone().into_owned()))
+    }
+
+    /// Add `item` to the batch `parent` if it's still open, otherwise return it.
+    fn add(&mut self, parent: Option<&[u8]>, item: Item) -> Option<Item> {
+        match parent.and_then(|parent| self.open.get_mut(parent)) {
+            Some(&mut (_, ref mut batch)) => {
+                batch.children.push(item);
+                None
+            }
+            None => Some(item),
+        }
+    }
+}
+
+#[cfg(test)]
+fn feed(tracker: &mut Tracker, lines: &[&[u8]]) -> Vec<Item> {
+    lines.iter()
+        .filter_map(|line| tracker.handle(&::parse_message(line).unwrap().unwrap().0))
+        .collect()
+}
+
+#[cfg(test)]
+fn owned(line: &[u8]) -> OwnedMessage {
+    ::parse_message(line).unwrap().unwrap().0.into_owned()
+}
+
+#[test]
+fn test_netsplit() {
+    let mut tracker = Tracker::new();
+    let items = feed(&mut tracker, &[
+        b":irc.host BATCH +yXNAbvnRHTRBv netsplit irc.hub other.host\r\n",
+        b"@batch=yXNAbvnRHTRBv :aji!a@a QUIT :irc.hub other.host\r\n",
+        b":nick!user@host PRIVMSG #channel :not in a batch\r\n",
+        b"@batch=yXNAbvnRHTRBv :nenolod!a@a QUIT :irc.hub other.host\r\n",
+    ]);
+    assert_eq!(items, vec![Item::Message(owned(b":nick!user@host PRIVMSG #channel :not in a batch\r\n"))]);
+    assert!(tracker.is_open(b"yXNAbvnRHTRBv"));
+
+    let items = feed(&mut tracker, &[b":irc.host BATCH -yXNAbvnRHTRBv\r\n"]);
+    assert_eq!(items, vec![Item::Batch(Batch {
+        reference: b"yXNAbvnRHTRBv".to_vec(),
+        batch_type: b"netsplit".to_vec(),
+        params: vec![b"irc.hub".to_vec(), b"other.host".to_vec()],
+        tags: vec![],
+        children: vec![
+            Item::Message(owned(b"@batch=yXNAbvnRHTRBv :aji!a@a QUIT :irc.hub other.host\r\n")),
+            Item::Message(owned(b"@batch=yXNAbvnRHTRBv :nenolod!a@a QUIT :irc.hub other.host\r\n")),
+        ],
+    })]);
+    assert!(!tracker.is_open(b"yXNAbvnRHTRBv"));
+}
+
+#[test]
+fn test_nested() {
+    let mut tracker = Tracker::new();
+    let items = feed(&mut tracker, &[
+        b"@label=abc :irc.host BATCH +outer labeled-response\r\n",
+        b"@batch=outer :irc.host BATCH +inner chathistory #channel\r\n",
+        b"@batch=inner :nick!user@host PRIVMSG #channel :hello\r\n",
+        b"@batch=outer :irc.host BATCH -inner\r\n",
+        b"@batch=unknown :nick!user@host PRIVMSG #channel :orphan\r\n",
+        b":irc.host BATCH -outer\r\n",
+    ]);
+    assert_eq!(items, vec![
+        Item::Message(owned(b"@batch=unknown :nick!user@host PRIVMSG #channel :orphan\r\n")),
+        Item::Batch(Batch {
+            reference: b"outer".to_vec(),
+            batch_type: b"labeled-response".to_vec(),
+            params: vec![],
+            tags: vec![(b"label".to_vec(), Some(b"abc".to_vec()))],
+            children: vec![Item::Batch(Batch {
+                reference: b"inner".to_vec(),
+                batch_type: b"chathistory".to_vec(),
+                params: vec![b"#channel".to_vec()],
+                tags: vec![(b"batch".to_vec(), Some(b"outer".to_vec()))],
+                children: vec![Item::Message(owned(b"@batch=inner :nick!user@host PRIVMSG #channel :hello\r\n"))],
+            })],
+        }),
+    ]);
+
+    // Closing a batch that isn't open yields the message itself.
+    let items = feed(&mut tracker, &[b":irc.host BATCH -outer\r\n"]);
+    assert_eq!(items, vec![Item::Message(owned(b":irc.host BATCH -outer\r\n"))]);
+}
+
+#[test]
+fn test_open_limit() {
+    let mut tracker = Tracker::new();
+    for i in 0..MAX_OPEN_BATCHES {
+        let line = format!(":irc.host BATCH +{} netsplit\r\n", i);
+        assert_eq!(feed(&mut tracker, &[line.as_bytes()]), vec![]);
+    }
+    let line = b":irc.host BATCH +extra netsplit\r\n";
+    assert_eq!(feed(&mut tracker, &[line]), vec![Item::Message(owned(line))]);
+    assert!(!tracker.is_open(b"extra"));
+    let line = b"@batch=extra :aji!a@a QUIT :bye\r\n";
+    assert_eq!(feed(&mut tracker, &[line]), vec![Item::Message(owned(line))]);
+}
@@ -0,0 +1,294 @@
+//! Sans-IO [capability negotiation](https://ircv3.net/specs/extensions/capability-negotiation).
+//!
+//! `Negotiator` consumes parsed messages and produces the messages that should be sent in
+//! response. It doesn't do any I/O, so it can be driven by both blocking and async clients.
+
+use std::collections::HashMap;
+
+use {Command, KnownCommand, Message, OwnedCommand, OwnedMessage, OwnedPrefix};
+
+/// Maximum length of the capability list in a single `CAP REQ`.
+const MAX_REQ_LEN: usize = 400;
+
+/// Negotiation progress.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum State {
+    /// `start` hasn't been called yet.
+    Idle,
+    /// Waiting for the server to finish listing its capabilities.
+    Listing,
+    /// Waiting for `ACK` or `NAK` replies.
+    Requesting,
+    /// All requests were answered but `CAP END` is being held back.
+    Held,
+    /// `CAP END` was sent.
+    Done,
+}
+
+/// Capability negotiation state machine.
+#[derive(Clone, Debug)]
+pub struct Negotiator {
+    state: State,
+    hold_end: bool,
+    wanted: Vec<Vec<u8>>,
+    available: HashMap<Vec<u8>, Vec<u8>>,
+    enabled: Vec<Vec<u8>>,
+    pending: Vec<Vec<u8>>,
+    rejected: Vec<Vec<u8>>,
+}
+
+fn cap_message(params: Vec<Vec<u8>>) -> OwnedMessage {
+    OwnedMessage {
+        tags: vec![],
+        prefix: OwnedPrefix::Implicit,
+        command: OwnedCommand::Command(KnownCommand::CAP),
+        params,
+    }
+}
+
+/// Split a capability list into `(name, value)` pairs.
+fn split_caps(caps: &[u8]) -> Vec<(&[u8], &[u8])> {
+    caps.split(|&b| b == b' ')
+        .filter(|cap| !cap.is_empty())
+        .map(|cap| {
+            let mut parts = cap.splitn(2, |&b| b == b'=');
+            (parts.next().unwrap_or(b""), parts.next().unwrap_or(b""))
+        })
+        .collect()
+}
+
+impl Negotiator {
+    /// Create a negotiator that requests the capabilities in `wanted` if the server supports
+    /// them.
+    pub fn new(wanted: &[&[u8]]) -> Negotiator {
+        Negotiator {
+            state: State::Idle,
+            hold_end: false,
+            wanted: wanted.iter().map(|cap| cap.to_vec()).collect(),
+            available: HashMap::new(),
+            enabled: vec![],
+            pending: vec![],
+            rejected: vec![],
+        }
+    }
+
+    /// Don't send `CAP END` automatically, e.g. because SASL authentication has to happen
+    /// first. Call `end` when ready.
+    pub fn hold_end(&mut self) {
+        self.hold_end = true;
+    }
+
+    /// Current state.
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Whether `CAP END` has been sent.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Whether the server supports `cap`.
+    pub fn is_available(&self, cap: &[u8]) -> bool {
+        self.available.contains_key(cap)
+    }
+
+    /// Value advertised for `cap`, e.g. `PLAIN,EXTERNAL` for `sasl=PLAIN,EXTERNAL`.
+    /// Capabilities without a value yield an empty slice.
+    pub fn value(&self, cap: &[u8]) -> Option<&[u8]> {
+        self.available.get(cap).map(|value| &value[..])
+    }
+
+    /// Whether `cap` has been acknowledged by the server.
+    pub fn is_enabled(&self, cap: &[u8]) -> bool {
+        self.enabled.iter().any(|enabled| &enabled[..] == cap)
+    }
+
+    /// Acknowledged capabilities.
+    pub fn enabled(&self) -> &[Vec<u8>] {
+        &self.enabled
+    }
+
+    /// Start negotiation. Returns `CAP LS 302`, which should be sent before `NICK` and `USER`.
+    pub fn start(&mut self) -> OwnedMessage {
+        self.state = State::Listing;
+        cap_message(vec![b"LS".to_vec(), b"302".to_vec()])
+    }
+
+    /// Finish negotiation held back by `hold_end`. Returns `CAP END` unless it was already
+    /// sent.
+    pub fn end(&mut self) -> Option<OwnedMessage> {
+        if self.state == State::Done {
+            return None;
+        }
+        self.state = State::Done;
+        Some(cap_message(vec![b"END".to_vec()]))
+    }
+
+    /// Process a message from the server. Returns the messages that should be sent in
+    /// response. Messages other than `CAP` are ignored.
+    pub fn handle(&mut self, message: &Message) -> Vec<OwnedMessage> {
+        if message.command != Command::Command(KnownCommand::CAP) || message.params.len() < 3 {
+            return vec![];
+        }
+
+        // The first parameter is the client's nickname or `*`.
+        let subcommand = message.params[1];
+        let caps = message.params[message.params.len() - 1];
+        let more = message.params.len() > 3 && message.params[2] == b"*";
+
+        match subcommand {
+            b"LS" => {
+                for (name, value) in split_caps(caps) {
+                    self.available.insert(name.to_vec(), value.to_vec());
+                }
+                if more || self.state != State::Listing {
+                    return vec![];
+                }
+                let mut out = self.request_wanted();
+                if out.is_empty() {
+                    out.extend(self.finish());
+                }
+                out
+            }
+            b"NEW" => {
+                for (name, value) in split_caps(caps) {
+                    self.available.insert(name.to_vec(), value.to_vec());
+                    // A re-advertised capability may be requested again.
+                    self.rejected.retain(|rejected| &rejected[..] != name);
+                }
+                self.request_wanted()
+            }
+            b"DEL" => {
+                for (name, _) in split_caps(caps) {
+                    self.available.remove(name);
+                    self.enabled.retain(|enabled| &enabled[..] != name);
+                }
+                vec![]
+            }
+            b"ACK" => {
+                for (name, _) in split_caps(caps) {
+                    if name.first() == Some(&b'-') {
+                        self.enabled.retain(|enabled| enabled[..] != name[1..]);
+                    } else if !self.is_enabled(name) {
+                        self.enabled.push(name.to_vec());
+                    }
+                    self.pending.retain(|pending| &pending[..] != name);
+                }
+                self.finish()
+            }
+            b"NAK" => {
+                for (name, _) in split_caps(caps) {
+                    self.pending.retain(|pending| &pending[..] != name);
+                    self.rejected.push(name.to_vec());
+                }
+                self.finish()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Request the wanted capabilities that are available but not enabled, pending or rejected.
+    fn request_wanted(&mut self) -> Vec<OwnedMessage> {
+        let request = self.wanted.iter()
+            .filter(|cap| self.available.contains_key(&cap[..]) && !self.is_enabled(cap))
+            .filter(|cap| !self.pending.contains(cap) && !self.rejected.contains(cap))
+            .cloned()
+            .collect::<Vec<_>>();
+        if request.is_empty() {
+            return vec![];
+        }
+
+        let mut out = vec![];
+        let mut line = Vec::<u8>::new();
+        for cap in &request {
+            if !line.is_empty() && line.len() + 1 + cap.len() > MAX_REQ_LEN {
+                out.push(cap_message(vec![b"REQ".to_vec(), line]));
+                line = vec![];
+            }
+            if !line.is_empty() {
+                line.push(b' ');
+            }
+            line.extend_from_slice(cap);
+        }
+        out.push(cap_message(vec![b"REQ".to_vec(), line]));
+
+        self.pending.extend(request);
+        if self.state == State::Listing {
+            self.state = State::Requesting;
+        }
+        out
+    }
+
+    /// Send `CAP END` if all requests during registration have been answered.
+    fn finish(&mut self) -> Vec<OwnedMessage> {
+        match self.state {
+            State::Listing | State::Requesting if self.pending.is_empty() => {
+                if self.hold_end {
+                    self.state = State::Held;
+                    vec![]
+                } else {
+                    self.end().into_iter().collect()
+                }
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+fn exchange(negotiator: &mut Negotiator, line: &[u8]) -> Vec<Vec<u8>> {
+    let msg = ::parse_message(line).unwrap().unwrap().0;
+    negotiator.handle(&msg).iter().map(|msg| msg.to_bytes().unwrap()).collect()
+}
+
+#[test]
+fn test_negotiation() {
+    let mut negotiator = Negotiator::new(&[b"multi-prefix", b"sasl", b"server-time", b"away-notify"]);
+    assert_eq!(negotiator.start().to_bytes().unwrap(), &b"CAP LS 302\r\n"[..]);
+
+    assert!(exchange(&mut negotiator, b":irc.example.com CAP * LS * :multi-prefix extended-join sasl=PLAIN,EXTERNAL\r\n").is_empty());
+    assert_eq!(exchange(&mut negotiator, b":irc.example.com CAP * LS :server-time account-notify\r\n"), vec![
+        b"CAP REQ :multi-prefix sasl server-time\r\n".to_vec(),
+    ]);
+    assert_eq!(negotiator.state(), State::Requesting);
+    assert_eq!(negotiator.value(b"sasl"), Some(&b"PLAIN,EXTERNAL"[..]));
+    assert_eq!(negotiator.value(b"extended-join"), Some(&b""[..]));
+    assert_eq!(negotiator.value(b"away-notify"), None);
+
+    assert!(exchange(&mut negotiator, b":irc.example.com CAP * ACK :multi-prefix sasl\r\n").is_empty());
+    assert_eq!(exchange(&mut negotiator, b":irc.example.com CAP * NAK :server-time\r\n"), vec![b"CAP END\r\n".to_vec()]);
+    assert!(negotiator.is_done());
+    assert_eq!(negotiator.enabled(), &[b"multi-prefix".to_vec(), b"sasl".to_vec()]);
+
+    // cap-notify after registration.
+    assert_eq!(exchange(&mut negotiator, b":irc.example.com CAP nick NEW :away-notify\r\n"), vec![
+        b"CAP REQ away-notify\r\n".to_vec(),
+    ]);
+    assert!(exchange(&mut negotiator, b":irc.example.com CAP nick ACK :away-notify\r\n").is_empty());
+    assert!(negotiator.is_enabled(b"away-notify"));
+    assert!(exchange(&mut negotiator, b":irc.example.com CAP nick DEL :away-notify sasl\r\n").is_empty());
+    assert!(!negotiator.is_enabled(b"away-notify"));
+    assert!(!negotiator.is_available(b"sasl"));
+    assert_eq!(negotiator.enabled(), &[b"multi-prefix".to_vec()]);
+}
+
+#[test]
+fn test_nothing_to_request() {
+    let mut negotiator = Negotiator::new(&[b"sasl"]);
+    negotiator.start();
+    assert_eq!(exchange(&mut negotiator, b":irc.example.com CAP * LS :multi-prefix\r\n"), vec![b"CAP END\r\n".to_vec()]);
+    assert!(negotiator.is_done());
+}
+
+#[test]
+fn test_hold_end() {
+    let mut negotiator = Negotiator::new(&[b"sasl"]);
+    negotiator.hold_end();
+    negotiator.start();
+    assert_eq!(exchange(&mut negotiator, b":irc.example.com CAP * LS :sasl\r\n"), vec![b"CAP REQ sasl\r\n".to_vec()]);
+    assert!(exchange(&mut negotiator, b":irc.example.com CAP * ACK :sasl\r\n").is_empty());
+    assert_eq!(negotiator.state(), State::Held);
+    assert_eq!(negotiator.end().unwrap().to_bytes().unwrap(), &b"CAP END\r\n"[..]);
+    assert_eq!(negotiator.end(), None);
+}
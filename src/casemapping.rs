@@ -0,0 +1,181 @@
+//! Case-insensitive comparison of nicknames and channel names according to the server's
+//! [`CASEMAPPING`](https://modern.ircdocs.horse/#casemapping-parameter).
+
+use std::borrow::Cow;
+use std::hash::{Hash, Hasher};
+use std::str;
+
+/// Rules for case-insensitive comparison. Defaults to `Rfc1459`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CaseMapping {
+    /// Only `A-Z` and `a-z` are equivalent.
+    Ascii,
+    /// Like `Ascii` but also ``[]\^`` and `{}|~` are equivalent.
+    #[default]
+    Rfc1459,
+    /// Like `Ascii` but also ``[]\`` and `{}|` are equivalent.
+    StrictRfc1459,
+    /// Unicode case-insensitive comparison from
+    /// [RFC 7613](https://tools.ietf.org/html/rfc7613). Normalization isn't applied, only Unicode
+    /// lowercasing. Invalid UTF-8 is compared like `Ascii`.
+    Rfc7613,
+}
+
+impl CaseMapping {
+    /// Look up a casemapping by its `CASEMAPPING` token value.
+    pub fn from_name(name: &[u8]) -> Option<CaseMapping> {
+        match name {
+            b"ascii" => Some(CaseMapping::Ascii),
+            b"rfc1459" => Some(CaseMapping::Rfc1459),
+            b"strict-rfc1459" => Some(CaseMapping::StrictRfc1459),
+            b"rfc7613" => Some(CaseMapping::Rfc7613),
+            _ => None,
+        }
+    }
+
+    /// Name of the casemapping as used in the `CASEMAPPING` token.
+    pub fn name(&self) -> &'static [u8] {
+        match *self {
+            CaseMapping::Ascii => b"ascii",
+            CaseMapping::Rfc1459 => b"rfc1459",
+            CaseMapping::StrictRfc1459 => b"strict-rfc1459",
+            CaseMapping::Rfc7613 => b"rfc7613",
+        }
+    }
+
+    /// Lowercase a single byte. `Rfc7613` only lowercases ASCII here.
+    pub fn fold_byte(&self, b: u8) -> u8 {
+        match (*self, b) {
+            (_, b'A'..=b'Z') => b.to_ascii_lowercase(),
+            (CaseMapping::Rfc1459, b'[') | (CaseMapping::StrictRfc1459, b'[') => b'{',
+            (CaseMapping::Rfc1459, b']') | (CaseMapping::StrictRfc1459, b']') => b'}',
+            (CaseMapping::Rfc1459, b'\\') | (CaseMapping::StrictRfc1459, b'\\') => b'|',
+            (CaseMapping::Rfc1459, b'^') => b'~',
+            _ => b,
+        }
+    }
+
+    fn fold_unicode(value: &[u8]) -> Option<String> {
+        match str::from_utf8(value) {
+            Ok(value) if !value.is_ascii() => Some(value.to_lowercase()),
+            _ => None,
+        }
+    }
+
+    /// Lowercase `value`.
+    pub fn fold<'a>(&self, value: &'a [u8]) -> Cow<'a, [u8]> {
+        if *self == CaseMapping::Rfc7613 {
+            if let Some(folded) = CaseMapping::fold_unicode(value) {
+                return Cow::Owned(folded.into_bytes());
+            }
+        }
+        if value.iter().all(|&b| self.fold_byte(b) == b) {
+            return Cow::Borrowed(value);
+        }
+        Cow::Owned(value.iter().map(|&b| self.fold_byte(b)).collect())
+    }
+
+    /// Compare two names case-insensitively.
+    pub fn eq_names(&self, a: &[u8], b: &[u8]) -> bool {
+        if *self == CaseMapping::Rfc7613 && !(a.is_ascii() && b.is_ascii()) {
+            return self.fold(a) == self.fold(b);
+        }
+        a.len() == b.len() && a.iter().zip(b).all(|(&a, &b)| self.fold_byte(a) == self.fold_byte(b))
+    }
+
+    /// Hash a name so that names that compare equal with `eq_names` have the same hash.
+    pub fn hash_name<H: Hasher>(&self, value: &[u8], state: &mut H) {
+        self.fold(value).hash(state)
+    }
+}
+
+/// Name that compares and hashes case-insensitively. Useful as a `HashMap` key.
+#[derive(Clone, Debug)]
+pub struct Key {
+    casemapping: CaseMapping,
+    name: Vec<u8>,
+    folded: Vec<u8>,
+}
+
+impl Key {
+    /// Create a key for `name`.
+    pub fn new(casemapping: CaseMapping, name: &[u8]) -> Key {
+        Key {
+            casemapping,
+            name: name.to_vec(),
+            folded: casemapping.fold(name).into_owned(),
+        }
+    }
+
+    /// Original name.
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// Lowercased name.
+    pub fn folded(&self) -> &[u8] {
+        &self.folded
+    }
+
+    /// Casemapping used to fold the name.
+    pub fn casemapping(&self) -> CaseMapping {
+        self.casemapping
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        self.folded == other.folded
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.folded.hash(state)
+    }
+}
+
+#[test]
+fn test_fold() {
+    assert_eq!(CaseMapping::Ascii.fold(b"Foo[away]^"), &b"foo[away]^"[..]);
+    assert_eq!(CaseMapping::Rfc1459.fold(b"Foo[away]\\^"), &b"foo{away}|~"[..]);
+    assert_eq!(CaseMapping::StrictRfc1459.fold(b"Foo[away]\\^"), &b"foo{away}|^"[..]);
+    assert_eq!(CaseMapping::Rfc7613.fold("ÄiTÖ[]".as_bytes()), "äitö[]".as_bytes());
+    match CaseMapping::Rfc1459.fold(b"foo{away}") {
+        Cow::Borrowed(b"foo{away}") => (),
+        e => panic!("Lowercase name has changed: {:?}", e),
+    }
+}
+
+#[test]
+fn test_eq_names() {
+    use std::collections::hash_map::DefaultHasher;
+
+    assert!(CaseMapping::Rfc1459.eq_names(b"Foo[away]", b"foo{away}"));
+    assert!(CaseMapping::Rfc1459.eq_names(b"a^", b"A~"));
+    assert!(!CaseMapping::StrictRfc1459.eq_names(b"a^", b"A~"));
+    assert!(!CaseMapping::Ascii.eq_names(b"Foo[away]", b"foo{away}"));
+    assert!(CaseMapping::Rfc7613.eq_names("ÄITI".as_bytes(), "äiti".as_bytes()));
+    assert!(!CaseMapping::Rfc1459.eq_names(b"foo", b"fooo"));
+
+    // The derived `PartialEq` and `Hash` still apply to the casemapping itself.
+    assert!(CaseMapping::Rfc1459.eq(&CaseMapping::default()));
+    let hash = |name: &[u8]| {
+        let mut hasher = DefaultHasher::new();
+        CaseMapping::Rfc1459.hash_name(name, &mut hasher);
+        hasher.finish()
+    };
+    assert_eq!(hash(b"Foo[away]"), hash(b"foo{away}"));
+}
+
+#[test]
+fn test_key() {
+    use std::collections::HashMap;
+
+    let mut nicks = HashMap::new();
+    nicks.insert(Key::new(CaseMapping::Rfc1459, b"Foo[away]"), 1);
+    assert_eq!(nicks.get(&Key::new(CaseMapping::Rfc1459, b"foo{away}")), Some(&1));
+    assert_eq!(nicks.keys().next().unwrap().name(), b"Foo[away]");
+}
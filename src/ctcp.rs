@@ -0,0 +1,114 @@
+//! [Client-to-Client Protocol](https://modern.ircdocs.horse/ctcp.html) messages embedded in
+//! `PRIVMSG` and `NOTICE`.
+
+use {Command, KnownCommand, Message, OwnedCommand, OwnedMessage, OwnedPrefix};
+
+const DELIMITER: u8 = 0x01;
+
+/// CTCP message such as `ACTION waves` or `VERSION`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Ctcp<'a> {
+    /// CTCP command, e.g. `ACTION`.
+    pub command: &'a [u8],
+    /// Everything after the command and the space following it.
+    pub params: Option<&'a [u8]>,
+}
+
+impl<'a> Ctcp<'a> {
+    /// Parse a CTCP message from a message body. The closing `\x01` is optional.
+    pub fn parse(text: &'a [u8]) -> Option<Ctcp<'a>> {
+        if text.first() != Some(&DELIMITER) {
+            return None;
+        }
+        let mut body = &text[1..];
+        if body.last() == Some(&DELIMITER) {
+            body = &body[..body.len() - 1];
+        }
+
+        let (command, params) = match body.iter().position(|&b| b == b' ') {
+            Some(idx) => (&body[..idx], Some(&body[idx + 1..])),
+            None => (body, None),
+        };
+        if command.is_empty() || command.contains(&DELIMITER) {
+            return None;
+        }
+        Some(Ctcp { command, params })
+    }
+
+    /// Extract a CTCP message from a `PRIVMSG` (a request) or a `NOTICE` (a reply).
+    pub fn from_message(message: &Message<'a>) -> Option<Ctcp<'a>> {
+        match message.command {
+            Command::Command(KnownCommand::PRIVMSG) | Command::Command(KnownCommand::NOTICE) => (),
+            _ => return None,
+        }
+        match message.params.len() {
+            2 => Ctcp::parse(message.params[1]),
+            _ => None,
+        }
+    }
+
+    /// Whether this is an `ACTION` (`/me`).
+    pub fn is_action(&self) -> bool {
+        self.command == b"ACTION"
+    }
+
+    /// Encode as a message body, including both delimiters.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(self.command.len() + self.params.map_or(0, |params| params.len() + 1) + 2);
+        body.push(DELIMITER);
+        body.extend_from_slice(self.command);
+        if let Some(params) = self.params {
+            body.push(b' ');
+            body.extend_from_slice(params);
+        }
+        body.push(DELIMITER);
+        body
+    }
+
+    fn build_message(&self, command: KnownCommand, target: &[u8]) -> OwnedMessage {
+        OwnedMessage {
+            tags: vec![],
+            prefix: OwnedPrefix::Implicit,
+            command: OwnedCommand::Command(command),
+            params: vec![target.to_vec(), self.to_bytes()],
+        }
+    }
+
+    /// Build a CTCP request to `target`, sent as a `PRIVMSG`.
+    pub fn request(&self, target: &[u8]) -> OwnedMessage {
+        self.build_message(KnownCommand::PRIVMSG, target)
+    }
+
+    /// Build a CTCP reply to `target`, sent as a `NOTICE`.
+    pub fn reply(&self, target: &[u8]) -> OwnedMessage {
+        self.build_message(KnownCommand::NOTICE, target)
+    }
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(Ctcp::parse(b"\x01ACTION waves\x01"), Some(Ctcp { command: b"ACTION", params: Some(b"waves") }));
+    assert_eq!(Ctcp::parse(b"\x01ACTION waves"), Some(Ctcp { command: b"ACTION", params: Some(b"waves") }));
+    assert_eq!(Ctcp::parse(b"\x01VERSION\x01"), Some(Ctcp { command: b"VERSION", params: None }));
+    assert_eq!(Ctcp::parse(b"\x01PING 1234567890\x01"), Some(Ctcp { command: b"PING", params: Some(b"1234567890") }));
+    assert_eq!(Ctcp::parse(b"\x01ACTION \x01"), Some(Ctcp { command: b"ACTION", params: Some(b"") }));
+    assert_eq!(Ctcp::parse(b"\x01\x01"), None);
+    assert_eq!(Ctcp::parse(b"\x01"), None);
+    assert_eq!(Ctcp::parse(b"ACTION waves"), None);
+}
+
+#[test]
+fn test_messages() {
+    let msg = ::parse_message(b":nick!ident@host.com PRIVMSG #channel :\x01ACTION waves\x01\r\n").unwrap().unwrap().0;
+    let ctcp = Ctcp::from_message(&msg).unwrap();
+    assert!(ctcp.is_action());
+    assert_eq!(ctcp.params, Some(&b"waves"[..]));
+
+    let msg = ::parse_message(b":nick!ident@host.com PRIVMSG #channel :waves\r\n").unwrap().unwrap().0;
+    assert_eq!(Ctcp::from_message(&msg), None);
+
+    let version = Ctcp { command: b"VERSION", params: Some(b"irc-syntax 0.1.0") };
+    assert_eq!(version.reply(b"nick").to_bytes().unwrap(), &b"NOTICE nick :\x01VERSION irc-syntax 0.1.0\x01\r\n"[..]);
+    let ping = Ctcp { command: b"PING", params: None };
+    assert_eq!(ping.request(b"nick").to_bytes().unwrap(), &b"PRIVMSG nick \x01PING\x01\r\n"[..]);
+}
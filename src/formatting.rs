@@ -0,0 +1,394 @@
+//! [mIRC formatting codes](https://modern.ircdocs.horse/formatting.html): parsing, stripping and
+//! rendering to ANSI terminal sequences and HTML.
+
+use std::borrow::Cow;
+use std::fmt::Write;
+
+/// Toggles bold.
+pub const BOLD: u8 = 0x02;
+/// Sets the colors from a palette: `\x03<fg>[,<bg>]`.
+pub const COLOR: u8 = 0x03;
+/// Sets the colors in hex: `\x04<RRGGBB>[,<RRGGBB>]`.
+pub const HEX_COLOR: u8 = 0x04;
+/// Resets all formatting.
+pub const RESET: u8 = 0x0F;
+/// Toggles monospace.
+pub const MONOSPACE: u8 = 0x11;
+/// Toggles reverse colors.
+pub const REVERSE: u8 = 0x16;
+/// Toggles italics.
+pub const ITALIC: u8 = 0x1D;
+/// Toggles strikethrough.
+pub const STRIKETHROUGH: u8 = 0x1E;
+/// Toggles underline.
+pub const UNDERLINE: u8 = 0x1F;
+
+/// Checks whether `b` starts a formatting code.
+pub fn is_format_code(b: u8) -> bool {
+    matches!(b, BOLD | COLOR | HEX_COLOR | RESET | MONOSPACE | REVERSE | ITALIC | STRIKETHROUGH | UNDERLINE)
+}
+
+/// Foreground or background color.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Color {
+    /// Color from the 99 color palette.
+    Palette(u8),
+    /// RGB color.
+    Rgb(u8, u8, u8),
+}
+
+const PALETTE: [u32; 99] = [
+    0xffffff, 0x000000, 0x00007f, 0x009300, 0xff0000, 0x7f0000, 0x9c009c, 0xfc7f00,
+    0xffff00, 0x00fc00, 0x009393, 0x00ffff, 0x0000fc, 0xff00ff, 0x7f7f7f, 0xd2d2d2,
+    0x470000, 0x472100, 0x474700, 0x324700, 0x004700, 0x00472c, 0x004747, 0x002747, 0x000047, 0x2e0047, 0x470047, 0x47002a,
+    0x740000, 0x743a00, 0x747400, 0x517400, 0x007400, 0x007449, 0x007474, 0x004074, 0x000074, 0x4b0074, 0x740074, 0x740045,
+    0xb50000, 0xb56300, 0xb5b500, 0x7db500, 0x00b500, 0x00b571, 0x00b5b5, 0x0063b5, 0x0000b5, 0x7500b5, 0xb500b5, 0xb5006b,
+    0xff0000, 0xff8c00, 0xffff00, 0xb2ff00, 0x00ff00, 0x00ffa0, 0x00ffff, 0x008cff, 0x0000ff, 0xa500ff, 0xff00ff, 0xff0098,
+    0xff5959, 0xffb459, 0xffff71, 0xcfff60, 0x6fff6f, 0x65ffc9, 0x6dffff, 0x59b4ff, 0x5959ff, 0xc459ff, 0xff66ff, 0xff59bc,
+    0xff9c9c, 0xffd39c, 0xffff9c, 0xe2ff9c, 0x9cff9c, 0x9cffdb, 0x9cffff, 0x9cd3ff, 0x9c9cff, 0xdc9cff, 0xff9cff, 0xff94d3,
+    0x000000, 0x131313, 0x282828, 0x363636, 0x4d4d4d, 0x656565, 0x818181, 0x9f9f9f, 0xbcbcbc, 0xe2e2e2, 0xffffff,
+];
+
+/// ANSI foreground colors closest to the first 16 palette colors.
+const ANSI: [u8; 16] = [97, 30, 34, 32, 91, 31, 35, 33, 93, 92, 36, 96, 94, 95, 90, 37];
+
+impl Color {
+    /// RGB value of the color.
+    pub fn rgb(&self) -> (u8, u8, u8) {
+        match *self {
+            Color::Palette(idx) => {
+                let rgb = PALETTE[idx as usize % PALETTE.len()];
+                ((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+            }
+            Color::Rgb(r, g, b) => (r, g, b),
+        }
+    }
+
+    fn write_ansi(&self, out: &mut Vec<u8>, background: bool) {
+        let offset = if background { 10 } else { 0 };
+        match *self {
+            Color::Palette(idx) if (idx as usize) < ANSI.len() => {
+                let _ = write!(Adapter(out), ";{}", ANSI[idx as usize] + offset);
+            }
+            _ => {
+                let (r, g, b) = self.rgb();
+                let _ = write!(Adapter(out), ";{};2;{};{};{}", 38 + offset, r, g, b);
+            }
+        }
+    }
+}
+
+/// Lets `write!` append to a byte buffer.
+struct Adapter<'a>(&'a mut Vec<u8>);
+
+impl<'a> Write for Adapter<'a> {
+    fn write_str(&mut self, s: &str) -> ::std::fmt::Result {
+        self.0.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Formatting applied to a span of text.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Style {
+    /// Bold.
+    pub bold: bool,
+    /// Italics.
+    pub italic: bool,
+    /// Underline.
+    pub underline: bool,
+    /// Strikethrough.
+    pub strikethrough: bool,
+    /// Monospace.
+    pub monospace: bool,
+    /// Foreground and background colors are swapped.
+    pub reverse: bool,
+    /// Foreground color or `None` for the default.
+    pub foreground: Option<Color>,
+    /// Background color or `None` for the default.
+    pub background: Option<Color>,
+}
+
+impl Style {
+    /// Whether this is the unformatted style.
+    pub fn is_plain(&self) -> bool {
+        *self == Style::default()
+    }
+}
+
+/// Text with a single style.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Span<'a> {
+    /// Formatting.
+    pub style: Style,
+    /// Text without formatting codes.
+    pub text: &'a [u8],
+}
+
+fn parse_palette_color(text: &[u8]) -> Option<(Option<Color>, usize)> {
+    let len = text.iter().take(2).take_while(|b| b.is_ascii_digit()).count();
+    if len == 0 {
+        return None;
+    }
+    let idx = text[..len].iter().fold(0, |idx, &b| idx * 10 + (b - b'0'));
+    // 99 is the default color.
+    let color = if idx == 99 { None } else { Some(Color::Palette(idx)) };
+    Some((color, len))
+}
+
+fn parse_hex_color(text: &[u8]) -> Option<(Option<Color>, usize)> {
+    if text.len() < 6 || !text[..6].iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let rgb = u32::from_str_radix(::std::str::from_utf8(&text[..6]).ok()?, 16).ok()?;
+    Some((Some(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)), 6))
+}
+
+/// Parse the colors after a color code. Returns the new colors and the length of the arguments.
+/// Without arguments both colors are reset.
+fn parse_colors<F>(text: &[u8], parse: F) -> (Option<Color>, Option<Option<Color>>, usize)
+where
+    F: Fn(&[u8]) -> Option<(Option<Color>, usize)>,
+{
+    let (foreground, len) = match parse(text) {
+        Some(fg) => fg,
+        None => return (None, Some(None), 0),
+    };
+    if text.get(len) == Some(&b',') {
+        if let Some((background, bg_len)) = parse(&text[len + 1..]) {
+            return (foreground, Some(background), len + 1 + bg_len);
+        }
+    }
+    (foreground, None, len)
+}
+
+/// Length of the formatting code at the start of `text`, including its arguments. Returns 0 if
+/// `text` doesn't start with a formatting code.
+pub fn format_code_len(text: &[u8]) -> usize {
+    match text.first() {
+        Some(&COLOR) => 1 + parse_colors(&text[1..], parse_palette_color).2,
+        Some(&HEX_COLOR) => 1 + parse_colors(&text[1..], parse_hex_color).2,
+        Some(&b) if is_format_code(b) => 1,
+        _ => 0,
+    }
+}
+
+/// Split formatted text into styled spans.
+pub fn parse(text: &[u8]) -> Vec<Span<'_>> {
+    let mut spans = vec![];
+    let mut style = Style::default();
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < text.len() {
+        let b = text[i];
+        if !is_format_code(b) {
+            i += 1;
+            continue;
+        }
+
+        if start < i {
+            spans.push(Span { style, text: &text[start..i] });
+        }
+        i += 1;
+        match b {
+            BOLD => style.bold = !style.bold,
+            ITALIC => style.italic = !style.italic,
+            UNDERLINE => style.underline = !style.underline,
+            STRIKETHROUGH => style.strikethrough = !style.strikethrough,
+            MONOSPACE => style.monospace = !style.monospace,
+            REVERSE => style.reverse = !style.reverse,
+            RESET => style = Style::default(),
+            COLOR | HEX_COLOR => {
+                let (foreground, background, len) = if b == COLOR {
+                    parse_colors(&text[i..], parse_palette_color)
+                } else {
+                    parse_colors(&text[i..], parse_hex_color)
+                };
+                style.foreground = foreground;
+                if let Some(background) = background {
+                    style.background = background;
+                }
+                i += len;
+            }
+            _ => unreachable!(),
+        }
+        start = i;
+    }
+
+    if start < text.len() {
+        spans.push(Span { style, text: &text[start..] });
+    }
+    spans
+}
+
+/// Remove all formatting codes.
+pub fn strip(text: &[u8]) -> Cow<'_, [u8]> {
+    if !text.iter().any(|&b| is_format_code(b)) {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(parse(text).iter().flat_map(|span| span.text.iter().cloned()).collect())
+}
+
+/// Render spans with ANSI terminal escape sequences.
+pub fn to_ansi(spans: &[Span]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut styled = false;
+    for span in spans {
+        if span.style.is_plain() {
+            if styled {
+                out.extend_from_slice(b"\x1b[0m");
+                styled = false;
+            }
+        } else {
+            let style = span.style;
+            out.extend_from_slice(b"\x1b[0");
+            if style.bold {
+                out.extend_from_slice(b";1");
+            }
+            if style.italic {
+                out.extend_from_slice(b";3");
+            }
+            if style.underline {
+                out.extend_from_slice(b";4");
+            }
+            if style.reverse {
+                out.extend_from_slice(b";7");
+            }
+            if style.strikethrough {
+                out.extend_from_slice(b";9");
+            }
+            if let Some(fg) = style.foreground {
+                fg.write_ansi(&mut out, false);
+            }
+            if let Some(bg) = style.background {
+                bg.write_ansi(&mut out, true);
+            }
+            out.push(b'm');
+            styled = true;
+        }
+        out.extend_from_slice(span.text);
+    }
+    if styled {
+        out.extend_from_slice(b"\x1b[0m");
+    }
+    out
+}
+
+fn escape_html(out: &mut String, text: &[u8]) {
+    for c in String::from_utf8_lossy(text).chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Render spans as HTML. Styled text is wrapped in `<span>` elements with inline styles. Invalid
+/// UTF-8 is replaced with U+FFFD.
+pub fn to_html(spans: &[Span]) -> String {
+    let mut out = String::new();
+    for span in spans {
+        let style = span.style;
+        if style.is_plain() {
+            escape_html(&mut out, span.text);
+            continue;
+        }
+
+        let mut css = vec![];
+        if style.bold {
+            css.push("font-weight:bold".to_string());
+        }
+        if style.italic {
+            css.push("font-style:italic".to_string());
+        }
+        match (style.underline, style.strikethrough) {
+            (true, true) => css.push("text-decoration:underline line-through".to_string()),
+            (true, false) => css.push("text-decoration:underline".to_string()),
+            (false, true) => css.push("text-decoration:line-through".to_string()),
+            (false, false) => (),
+        }
+        if style.monospace {
+            css.push("font-family:monospace".to_string());
+        }
+        let (fg, bg) = if style.reverse {
+            (style.background, style.foreground)
+        } else {
+            (style.foreground, style.background)
+        };
+        if let Some(fg) = fg {
+            let (r, g, b) = fg.rgb();
+            css.push(format!("color:#{:02x}{:02x}{:02x}", r, g, b));
+        }
+        if let Some(bg) = bg {
+            let (r, g, b) = bg.rgb();
+            css.push(format!("background-color:#{:02x}{:02x}{:02x}", r, g, b));
+        }
+
+        out.push_str("<span style=\"");
+        out.push_str(&css.join(";"));
+        out.push_str("\">");
+        escape_html(&mut out, span.text);
+        out.push_str("</span>");
+    }
+    out
+}
+
+#[test]
+fn test_parse() {
+    let bold = Style { bold: true, ..Style::default() };
+    assert_eq!(parse(b"plain \x02bold\x02 plain"), vec![
+        Span { style: Style::default(), text: b"plain " },
+        Span { style: bold, text: b"bold" },
+        Span { style: Style::default(), text: b" plain" },
+    ]);
+
+    let red_on_blue = Style { foreground: Some(Color::Palette(4)), background: Some(Color::Palette(2)), ..Style::default() };
+    let red_on_blue_italic = Style { italic: true, ..red_on_blue };
+    assert_eq!(parse(b"\x034,02red\x1Ditalic\x03,text\x0Freset"), vec![
+        Span { style: red_on_blue, text: b"red" },
+        Span { style: red_on_blue_italic, text: b"italic" },
+        Span { style: Style { italic: true, ..Style::default() }, text: b",text" },
+        Span { style: Style::default(), text: b"reset" },
+    ]);
+
+    // Only up to two digits are part of the color.
+    assert_eq!(parse(b"\x03123"), vec![
+        Span { style: Style { foreground: Some(Color::Palette(12)), ..Style::default() }, text: b"3" },
+    ]);
+    // 99 is the default color.
+    assert_eq!(parse(b"\x0399,99x"), vec![Span { style: Style::default(), text: b"x" }]);
+
+    assert_eq!(parse(b"\x04FF8000,000000x\x04y"), vec![
+        Span { style: Style { foreground: Some(Color::Rgb(0xff, 0x80, 0x00)), background: Some(Color::Rgb(0, 0, 0)), ..Style::default() }, text: b"x" },
+        Span { style: Style::default(), text: b"y" },
+    ]);
+
+    assert_eq!(format_code_len(b"\x0304,02text"), 6);
+    assert_eq!(format_code_len(b"\x03,02text"), 1);
+    assert_eq!(format_code_len(b"\x1Ftext"), 1);
+    assert_eq!(format_code_len(b"text"), 0);
+}
+
+#[test]
+fn test_strip() {
+    assert_eq!(strip(b"\x02bold\x02 \x0304,02red\x03 \x1Ditalic\x1D\x1F\x1E\x11\x16\x0F"), &b"bold red italic"[..]);
+    match strip(b"no formatting") {
+        Cow::Borrowed(b"no formatting") => (),
+        e => panic!("Text with no formatting has changed: {:?}", e),
+    }
+}
+
+#[test]
+fn test_render() {
+    let spans = parse(b"a\x02b\x0304,52c\x0Fd");
+    assert_eq!(to_ansi(&spans), &b"a\x1b[0;1mb\x1b[0;1;91;48;2;255;0;0mc\x1b[0md"[..]);
+    assert_eq!(to_ansi(&parse(b"plain")), &b"plain"[..]);
+
+    assert_eq!(to_html(&spans), "a<span style=\"font-weight:bold\">b</span><span style=\"font-weight:bold;color:#ff0000;background-color:#ff0000\">c</span>d");
+    assert_eq!(to_html(&parse(b"\x1F\x1E<&>")), "<span style=\"text-decoration:underline line-through\">&lt;&amp;&gt;</span>");
+}
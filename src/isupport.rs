@@ -0,0 +1,249 @@
+//! Server features advertised with [`RPL_ISUPPORT`](https://modern.ircdocs.horse/#rplisupport-005).
+
+use std::collections::HashMap;
+
+use casemapping::CaseMapping;
+use {Command, Message, Reply};
+
+/// Channel modes grouped by [`CHANMODES`](https://modern.ircdocs.horse/#chanmodes-parameter) type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChanModes {
+    /// Type A: modes that add or remove an address to or from a list. Always take a parameter.
+    pub a: Vec<u8>,
+    /// Type B: modes that change a setting. Always take a parameter.
+    pub b: Vec<u8>,
+    /// Type C: modes that change a setting. Only take a parameter when set.
+    pub c: Vec<u8>,
+    /// Type D: modes that change a setting. Never take a parameter.
+    pub d: Vec<u8>,
+}
+
+/// Tokens collected from one or more `RPL_ISUPPORT` messages.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ISupport {
+    tokens: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decode `\xHH` escapes in a token value.
+fn unescape_value(value: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(value.len());
+    let mut i = 0;
+    while i < value.len() {
+        if value[i..].starts_with(b"\\x") && i + 4 <= value.len() {
+            if let (Some(hi), Some(lo)) = (hex_digit(value[i + 2]), hex_digit(value[i + 3])) {
+                unescaped.push(hi << 4 | lo);
+                i += 4;
+                continue;
+            }
+        }
+        unescaped.push(value[i]);
+        i += 1;
+    }
+    unescaped
+}
+
+/// Whether `param` looks like a token: an optional `-`, an uppercase name and optionally `=`
+/// followed by a value without spaces.
+fn is_token(param: &[u8]) -> bool {
+    let param = if param.first() == Some(&b'-') { &param[1..] } else { param };
+    let name_len = param.iter().take_while(|b| b.is_ascii_uppercase() || b.is_ascii_digit()).count();
+    name_len > 0 && (name_len == param.len() || param[name_len] == b'=') && !param.contains(&b' ')
+}
+
+impl ISupport {
+    /// Create an empty set of tokens.
+    pub fn new() -> ISupport {
+        ISupport::default()
+    }
+
+    /// Add the tokens from an `RPL_ISUPPORT` message. Returns `false` and does nothing if the
+    /// message isn't `RPL_ISUPPORT`.
+    pub fn add_message(&mut self, message: &Message) -> bool {
+        if message.command != Command::Reply(Reply::ISUPPORT) {
+            return false;
+        }
+
+        // The first parameter is the client's nickname and the last one is usually a
+        // human-readable "are supported by this server", but some servers leave it out.
+        let params = match message.params.split_last() {
+            Some((last, params)) if last.contains(&b' ') || (params.len() > 1 && !is_token(last)) => params,
+            _ => &message.params[..],
+        };
+        for token in params.iter().skip(1) {
+            self.add_token(token);
+        }
+        true
+    }
+
+    /// Add a single token such as `NICKLEN=30`, `EXCEPTS` or `-TARGMAX`.
+    pub fn add_token(&mut self, token: &[u8]) {
+        if token.first() == Some(&b'-') {
+            self.tokens.remove(&token[1..]);
+            return;
+        }
+
+        let mut parts = token.splitn(2, |&b| b == b'=');
+        let key = parts.next().unwrap_or(b"");
+        if key.is_empty() {
+            return;
+        }
+        let value = parts.next().map_or_else(Vec::new, unescape_value);
+        self.tokens.insert(key.to_vec(), value);
+    }
+
+    /// Whether the server advertised `key`.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.tokens.contains_key(key)
+    }
+
+    /// Value of the token `key`. Tokens without a value yield an empty slice.
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.tokens.get(key).map(|value| &value[..])
+    }
+
+    fn get_number(&self, key: &[u8]) -> Option<usize> {
+        ::std::str::from_utf8(self.get(key)?).ok()?.parse().ok()
+    }
+
+    /// `CASEMAPPING` token. Defaults to `rfc1459`, which is also used for unknown casemappings.
+    pub fn casemapping(&self) -> CaseMapping {
+        self.get(b"CASEMAPPING").and_then(CaseMapping::from_name).unwrap_or_default()
+    }
+
+    /// `CHANTYPES` token. Defaults to `#&`.
+    pub fn chantypes(&self) -> &[u8] {
+        self.get(b"CHANTYPES").unwrap_or(b"#&")
+    }
+
+    /// `PREFIX` token as `(mode, prefix)` pairs ordered from the highest rank. Defaults to
+    /// `(ov)@+`.
+    pub fn prefix(&self) -> Vec<(u8, u8)> {
+        let value = match self.get(b"PREFIX") {
+            Some(value) => value,
+            None => return vec![(b'o', b'@'), (b'v', b'+')],
+        };
+        if value.first() != Some(&b'(') {
+            return vec![];
+        }
+        let close = match value.iter().position(|&b| b == b')') {
+            Some(close) => close,
+            None => return vec![],
+        };
+        value[1..close].iter().cloned().zip(value[close + 1..].iter().cloned()).collect()
+    }
+
+    /// `CHANMODES` token. Defaults to the modes from RFC 2811.
+    pub fn chanmodes(&self) -> ChanModes {
+        let value = match self.get(b"CHANMODES") {
+            Some(value) => value,
+            None => return ChanModes {
+                a: b"beI".to_vec(),
+                b: b"k".to_vec(),
+                c: b"l".to_vec(),
+                d: b"aimnqpsrt".to_vec(),
+            },
+        };
+        let mut groups = value.split(|&b| b == b',');
+        let mut next = || groups.next().map_or_else(Vec::new, <[u8]>::to_vec);
+        ChanModes {
+            a: next(),
+            b: next(),
+            c: next(),
+            d: next(),
+        }
+    }
+
+    /// `NICKLEN` token. Defaults to 9.
+    pub fn nicklen(&self) -> usize {
+        self.get_number(b"NICKLEN").unwrap_or(9)
+    }
+
+    /// `TARGMAX` token as `(command, limit)` pairs. A limit of `None` means there's no limit.
+    pub fn targmax(&self) -> Vec<(&[u8], Option<usize>)> {
+        let value = match self.get(b"TARGMAX") {
+            Some(value) if !value.is_empty() => value,
+            _ => return vec![],
+        };
+        value.split(|&b| b == b',')
+            .filter_map(|target| {
+                let mut parts = target.splitn(2, |&b| b == b':');
+                let command = parts.next()?;
+                let limit = parts.next()
+                    .and_then(|limit| ::std::str::from_utf8(limit).ok())
+                    .and_then(|limit| limit.parse().ok());
+                Some((command, limit))
+            })
+            .collect()
+    }
+
+    /// `STATUSMSG` token. Defaults to no prefixes.
+    pub fn statusmsg(&self) -> &[u8] {
+        self.get(b"STATUSMSG").unwrap_or(b"")
+    }
+
+    /// `NETWORK` token.
+    pub fn network(&self) -> Option<&[u8]> {
+        self.get(b"NETWORK")
+    }
+}
+
+#[test]
+fn test_isupport() {
+    let mut isupport = ISupport::new();
+    assert_eq!(isupport.casemapping(), CaseMapping::Rfc1459);
+    assert_eq!(isupport.chantypes(), b"#&");
+    assert_eq!(isupport.prefix(), vec![(b'o', b'@'), (b'v', b'+')]);
+    assert_eq!(isupport.nicklen(), 9);
+
+    let lines: &[&[u8]] = &[
+        b":irc.example.com 005 nick CASEMAPPING=ascii CHANTYPES=# PREFIX=(qaohv)~&@%+ CHANMODES=beI,k,l,imnpst :are supported by this server\r\n",
+        b":irc.example.com 005 nick NICKLEN=30 TARGMAX=NAMES:1,PRIVMSG:4,JOIN: STATUSMSG=@+ NETWORK=Example\\x20Network EXCEPTS :are supported by this server\r\n",
+    ];
+    for line in lines {
+        let msg = ::parse_message(line).unwrap().unwrap().0;
+        assert!(isupport.add_message(&msg));
+    }
+
+    assert_eq!(isupport.casemapping(), CaseMapping::Ascii);
+    assert_eq!(isupport.chantypes(), b"#");
+    assert_eq!(isupport.prefix(), vec![(b'q', b'~'), (b'a', b'&'), (b'o', b'@'), (b'h', b'%'), (b'v', b'+')]);
+    assert_eq!(isupport.chanmodes(), ChanModes {
+        a: b"beI".to_vec(),
+        b: b"k".to_vec(),
+        c: b"l".to_vec(),
+        d: b"imnpst".to_vec(),
+    });
+    assert_eq!(isupport.nicklen(), 30);
+    assert_eq!(isupport.targmax(), vec![(&b"NAMES"[..], Some(1)), (&b"PRIVMSG"[..], Some(4)), (&b"JOIN"[..], None)]);
+    assert_eq!(isupport.statusmsg(), b"@+");
+    assert_eq!(isupport.network(), Some(&b"Example Network"[..]));
+    assert_eq!(isupport.get(b"EXCEPTS"), Some(&b""[..]));
+
+    let msg = ::parse_message(b":irc.example.com 005 nick -EXCEPTS -NICKLEN :are supported by this server\r\n").unwrap().unwrap().0;
+    assert!(isupport.add_message(&msg));
+    assert!(!isupport.contains(b"EXCEPTS"));
+    assert_eq!(isupport.nicklen(), 9);
+
+    // Without the trailing text the last parameter is a token too.
+    let msg = ::parse_message(b":s 005 nick NICKLEN=30\r\n").unwrap().unwrap().0;
+    assert!(isupport.add_message(&msg));
+    assert_eq!(isupport.nicklen(), 30);
+    let msg = ::parse_message(b":s 005 nick NETWORK=Example CHANTYPES=#\r\n").unwrap().unwrap().0;
+    assert!(isupport.add_message(&msg));
+    assert_eq!(isupport.chantypes(), b"#");
+    let msg = ::parse_message(b":s 005 nick -NETWORK supported\r\n").unwrap().unwrap().0;
+    assert!(isupport.add_message(&msg));
+    assert!(!isupport.contains(b"supported") && isupport.network().is_none());
+
+    let msg = ::parse_message(b":irc.example.com 001 nick :Welcome\r\n").unwrap().unwrap().0;
+    assert!(!isupport.add_message(&msg));
+}
@@ -2,25 +2,45 @@
 
 //! IRC message parser loosely inspired by [RFC 2812](https://tools.ietf.org/html/rfc2812).
 
+extern crate base64;
+extern crate hmac;
 #[macro_use]
 extern crate nom;
-extern crate twoway;
+extern crate sha2;
 
 use nom::{alpha, digit, IResult};
 use std::borrow::Cow;
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::IpAddr;
+
+pub mod batch;
+pub mod cap;
+pub mod casemapping;
+pub mod ctcp;
+pub mod formatting;
+pub mod isupport;
+pub mod mask;
+pub mod mode;
+pub mod sasl;
+pub mod split;
+pub mod time;
+pub mod twitch;
 
 /// Message source.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Prefix<'a> {
-    /// Message was sent by a server.
+    /// Message was sent by a server. A bare name is only treated as a server name if it contains
+    /// a `.`, which nicknames can't.
     Server(&'a [u8]),
-    /// Message was sent by a user.
+    /// Message was sent by a user. Bare names without a `.` are treated as nicknames.
     User {
         /// User's nickname.
         nick: &'a [u8],
         /// User's username.
         user: Option<&'a [u8]>,
-        /// User's hostname.
+        /// User's hostname, IP address or cloak.
         host: Option<&'a [u8]>,
     },
     /// Prefix was missing.
@@ -36,6 +56,7 @@ pub enum Reply {
     YOURHOST = 2,
     CREATED = 3,
     MYINFO = 4,
+    /// Conflicts with `RPL_ISUPPORT`, see `Reply::ISUPPORT`.
     BOUNCE = 5,
     TRACELINK = 200,
     TRACECONNECTING = 201,
@@ -70,6 +91,7 @@ pub enum Reply {
     STATSHLINE = 244,
     STATSPING = 246,
     STATSBLINE = 247,
+    /// Conflicts with `RPL_STATSCONN`, see `Reply::STATSCONN`.
     STATSDLINE = 250,
     LUSERCLIENT = 251,
     LUSEROP = 252,
@@ -83,12 +105,17 @@ pub enum Reply {
     TRACELOG = 261,
     TRACEEND = 262,
     TRYAGAIN = 263,
+    LOCALUSERS = 265,
+    GLOBALUSERS = 266,
+    WHOISCERTFP = 276,
     NONE = 300,
     AWAY = 301,
     USERHOST = 302,
     ISON = 303,
     UNAWAY = 305,
     NOWAWAY = 306,
+    /// Conflicts with `RPL_USERIP` on some servers.
+    WHOISREGNICK = 307,
     WHOISUSER = 311,
     WHOISSERVER = 312,
     WHOISOPERATOR = 313,
@@ -98,13 +125,22 @@ pub enum Reply {
     WHOISIDLE = 317,
     ENDOFWHOIS = 318,
     WHOISCHANNELS = 319,
+    /// Format differs between server implementations.
+    WHOISSPECIAL = 320,
     LISTSTART = 321,
     LIST = 322,
     LISTEND = 323,
     CHANNELMODEIS = 324,
     UNIQOPIS = 325,
+    CREATIONTIME = 329,
+    /// Conflicts with `RPL_WHOWAS_TIME` on ircu.
+    WHOISACCOUNT = 330,
     NOTOPIC = 331,
     TOPIC = 332,
+    TOPICWHOTIME = 333,
+    WHOISBOT = 335,
+    /// Format differs between server implementations.
+    WHOISACTUALLY = 338,
     INVITING = 341,
     SUMMONING = 342,
     INVITELIST = 346,
@@ -114,6 +150,7 @@ pub enum Reply {
     VERSION = 351,
     WHOREPLY = 352,
     NAMREPLY = 353,
+    WHOSPCRPL = 354,
     KILLDONE = 361,
     CLOSING = 362,
     CLOSEEND = 363,
@@ -129,6 +166,8 @@ pub enum Reply {
     ENDOFINFO = 374,
     MOTDSTART = 375,
     ENDOFMOTD = 376,
+    WHOISHOST = 378,
+    WHOISMODES = 379,
     YOUREOPER = 381,
     REHASHING = 382,
     YOURESERVICE = 383,
@@ -138,9 +177,34 @@ pub enum Reply {
     USERS = 393,
     ENDOFUSERS = 394,
     NOUSERS = 395,
+    /// Also known as `RPL_HOSTHIDDEN`.
+    VISIBLEHOST = 396,
+    STARTTLS = 670,
+    WHOISSECURE = 671,
+    HELPSTART = 704,
+    HELPTXT = 705,
+    ENDOFHELP = 706,
+    MONONLINE = 730,
+    MONOFFLINE = 731,
+    MONLIST = 732,
+    ENDOFMONLIST = 733,
+    LOGGEDIN = 900,
+    LOGGEDOUT = 901,
+    SASLSUCCESS = 903,
+    SASLMECHS = 908,
 }
 
 impl Reply {
+    /// `RPL_ISUPPORT`. Conflicts with `RPL_BOUNCE` from RFC 2812 but nearly every server sends
+    /// 005 as `RPL_ISUPPORT`.
+    pub const ISUPPORT: Reply = Reply::BOUNCE;
+    /// `RPL_STATSCONN`. Conflicts with `RPL_STATSDLINE` from RFC 2812.
+    pub const STATSCONN: Reply = Reply::STATSDLINE;
+    /// `RPL_INVEXLIST`. Modern servers use 346 for the invite exception list.
+    pub const INVEXLIST: Reply = Reply::INVITELIST;
+    /// `RPL_ENDOFINVEXLIST`. Modern servers use 347 for the invite exception list.
+    pub const ENDOFINVEXLIST: Reply = Reply::ENDOFINVITELIST;
+
     fn from(data: u16) -> Option<Reply> {
         match data {
             1 => Some(Reply::WELCOME),
@@ -194,12 +258,16 @@ impl Reply {
             261 => Some(Reply::TRACELOG),
             262 => Some(Reply::TRACEEND),
             263 => Some(Reply::TRYAGAIN),
+            265 => Some(Reply::LOCALUSERS),
+            266 => Some(Reply::GLOBALUSERS),
+            276 => Some(Reply::WHOISCERTFP),
             300 => Some(Reply::NONE),
             301 => Some(Reply::AWAY),
             302 => Some(Reply::USERHOST),
             303 => Some(Reply::ISON),
             305 => Some(Reply::UNAWAY),
             306 => Some(Reply::NOWAWAY),
+            307 => Some(Reply::WHOISREGNICK),
             311 => Some(Reply::WHOISUSER),
             312 => Some(Reply::WHOISSERVER),
             313 => Some(Reply::WHOISOPERATOR),
@@ -209,13 +277,19 @@ impl Reply {
             317 => Some(Reply::WHOISIDLE),
             318 => Some(Reply::ENDOFWHOIS),
             319 => Some(Reply::WHOISCHANNELS),
+            320 => Some(Reply::WHOISSPECIAL),
             321 => Some(Reply::LISTSTART),
             322 => Some(Reply::LIST),
             323 => Some(Reply::LISTEND),
             324 => Some(Reply::CHANNELMODEIS),
             325 => Some(Reply::UNIQOPIS),
+            329 => Some(Reply::CREATIONTIME),
+            330 => Some(Reply::WHOISACCOUNT),
             331 => Some(Reply::NOTOPIC),
             332 => Some(Reply::TOPIC),
+            333 => Some(Reply::TOPICWHOTIME),
+            335 => Some(Reply::WHOISBOT),
+            338 => Some(Reply::WHOISACTUALLY),
             341 => Some(Reply::INVITING),
             342 => Some(Reply::SUMMONING),
             346 => Some(Reply::INVITELIST),
@@ -225,6 +299,7 @@ impl Reply {
             351 => Some(Reply::VERSION),
             352 => Some(Reply::WHOREPLY),
             353 => Some(Reply::NAMREPLY),
+            354 => Some(Reply::WHOSPCRPL),
             361 => Some(Reply::KILLDONE),
             362 => Some(Reply::CLOSING),
             363 => Some(Reply::CLOSEEND),
@@ -240,6 +315,8 @@ impl Reply {
             374 => Some(Reply::ENDOFINFO),
             375 => Some(Reply::MOTDSTART),
             376 => Some(Reply::ENDOFMOTD),
+            378 => Some(Reply::WHOISHOST),
+            379 => Some(Reply::WHOISMODES),
             381 => Some(Reply::YOUREOPER),
             382 => Some(Reply::REHASHING),
             383 => Some(Reply::YOURESERVICE),
@@ -249,6 +326,20 @@ impl Reply {
             393 => Some(Reply::USERS),
             394 => Some(Reply::ENDOFUSERS),
             395 => Some(Reply::NOUSERS),
+            396 => Some(Reply::VISIBLEHOST),
+            670 => Some(Reply::STARTTLS),
+            671 => Some(Reply::WHOISSECURE),
+            704 => Some(Reply::HELPSTART),
+            705 => Some(Reply::HELPTXT),
+            706 => Some(Reply::ENDOFHELP),
+            730 => Some(Reply::MONONLINE),
+            731 => Some(Reply::MONOFFLINE),
+            732 => Some(Reply::MONLIST),
+            733 => Some(Reply::ENDOFMONLIST),
+            900 => Some(Reply::LOGGEDIN),
+            901 => Some(Reply::LOGGEDOUT),
+            903 => Some(Reply::SASLSUCCESS),
+            908 => Some(Reply::SASLMECHS),
             _ => None,
         }
     }
@@ -259,6 +350,7 @@ impl Reply {
 #[allow(missing_docs)]
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Error {
+    UNKNOWNERROR = 400,
     NOSUCHNICK = 401,
     NOSUCHSERVER = 402,
     NOSUCHCHANNEL = 403,
@@ -273,6 +365,7 @@ pub enum Error {
     NOTOPLEVEL = 413,
     WILDTOPLEVEL = 414,
     BADMASK = 415,
+    INPUTTOOLONG = 417,
     UNKNOWNCOMMAND = 421,
     NOMOTD = 422,
     NOADMININFO = 423,
@@ -313,11 +406,23 @@ pub enum Error {
     NOSERVICEHOST = 492,
     UMODEUNKNOWNFLAG = 501,
     USERSDONTMATCH = 502,
+    HELPNOTFOUND = 524,
+    INVALIDKEY = 525,
+    STARTTLS = 691,
+    INVALIDMODEPARAM = 696,
+    NOPRIVS = 723,
+    MONLISTFULL = 734,
+    NICKLOCKED = 902,
+    SASLFAIL = 904,
+    SASLTOOLONG = 905,
+    SASLABORTED = 906,
+    SASLALREADY = 907,
 }
 
 impl Error {
     fn from(data: u16) -> Option<Error> {
         match data {
+            400 => Some(Error::UNKNOWNERROR),
             401 => Some(Error::NOSUCHNICK),
             402 => Some(Error::NOSUCHSERVER),
             403 => Some(Error::NOSUCHCHANNEL),
@@ -332,6 +437,7 @@ impl Error {
             413 => Some(Error::NOTOPLEVEL),
             414 => Some(Error::WILDTOPLEVEL),
             415 => Some(Error::BADMASK),
+            417 => Some(Error::INPUTTOOLONG),
             421 => Some(Error::UNKNOWNCOMMAND),
             422 => Some(Error::NOMOTD),
             423 => Some(Error::NOADMININFO),
@@ -372,6 +478,17 @@ impl Error {
             492 => Some(Error::NOSERVICEHOST),
             501 => Some(Error::UMODEUNKNOWNFLAG),
             502 => Some(Error::USERSDONTMATCH),
+            524 => Some(Error::HELPNOTFOUND),
+            525 => Some(Error::INVALIDKEY),
+            691 => Some(Error::STARTTLS),
+            696 => Some(Error::INVALIDMODEPARAM),
+            723 => Some(Error::NOPRIVS),
+            734 => Some(Error::MONLISTFULL),
+            902 => Some(Error::NICKLOCKED),
+            904 => Some(Error::SASLFAIL),
+            905 => Some(Error::SASLTOOLONG),
+            906 => Some(Error::SASLABORTED),
+            907 => Some(Error::SASLALREADY),
             _ => None,
         }
     }
@@ -426,6 +543,21 @@ pub enum KnownCommand {
     WALLOPS,
     USERHOST,
     ISON,
+    // IRCv3
+    CAP,
+    AUTHENTICATE,
+    TAGMSG,
+    BATCH,
+    ACCOUNT,
+    CHGHOST,
+    SETNAME,
+    MONITOR,
+    FAIL,
+    WARN,
+    NOTE,
+    CHATHISTORY,
+    WEBIRC,
+    STARTTLS,
 }
 
 impl KnownCommand {
@@ -476,9 +608,88 @@ impl KnownCommand {
             b"WALLOPS" => Some(KnownCommand::WALLOPS),
             b"USERHOST" => Some(KnownCommand::USERHOST),
             b"ISON" => Some(KnownCommand::ISON),
+            b"CAP" => Some(KnownCommand::CAP),
+            b"AUTHENTICATE" => Some(KnownCommand::AUTHENTICATE),
+            b"TAGMSG" => Some(KnownCommand::TAGMSG),
+            b"BATCH" => Some(KnownCommand::BATCH),
+            b"ACCOUNT" => Some(KnownCommand::ACCOUNT),
+            b"CHGHOST" => Some(KnownCommand::CHGHOST),
+            b"SETNAME" => Some(KnownCommand::SETNAME),
+            b"MONITOR" => Some(KnownCommand::MONITOR),
+            b"FAIL" => Some(KnownCommand::FAIL),
+            b"WARN" => Some(KnownCommand::WARN),
+            b"NOTE" => Some(KnownCommand::NOTE),
+            b"CHATHISTORY" => Some(KnownCommand::CHATHISTORY),
+            b"WEBIRC" => Some(KnownCommand::WEBIRC),
+            b"STARTTLS" => Some(KnownCommand::STARTTLS),
             _ => None,
         }
     }
+
+    /// Command name as it appears on the wire.
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match *self {
+            KnownCommand::PASS => b"PASS",
+            KnownCommand::NICK => b"NICK",
+            KnownCommand::USER => b"USER",
+            KnownCommand::OPER => b"OPER",
+            KnownCommand::MODE => b"MODE",
+            KnownCommand::SERVICE => b"SERVICE",
+            KnownCommand::QUIT => b"QUIT",
+            KnownCommand::SQUIT => b"SQUIT",
+            KnownCommand::JOIN => b"JOIN",
+            KnownCommand::PART => b"PART",
+            KnownCommand::TOPIC => b"TOPIC",
+            KnownCommand::NAMES => b"NAMES",
+            KnownCommand::LIST => b"LIST",
+            KnownCommand::INVITE => b"INVITE",
+            KnownCommand::KICK => b"KICK",
+            KnownCommand::PRIVMSG => b"PRIVMSG",
+            KnownCommand::NOTICE => b"NOTICE",
+            KnownCommand::MOTD => b"MOTD",
+            KnownCommand::LUSERS => b"LUSERS",
+            KnownCommand::VERSION => b"VERSION",
+            KnownCommand::STATS => b"STATS",
+            KnownCommand::LINKS => b"LINKS",
+            KnownCommand::TIME => b"TIME",
+            KnownCommand::CONNECT => b"CONNECT",
+            KnownCommand::TRACE => b"TRACE",
+            KnownCommand::ADMIN => b"ADMIN",
+            KnownCommand::INFO => b"INFO",
+            KnownCommand::SERVLIST => b"SERVLIST",
+            KnownCommand::SQUERY => b"SQUERY",
+            KnownCommand::WHO => b"WHO",
+            KnownCommand::WHOIS => b"WHOIS",
+            KnownCommand::WHOWAS => b"WHOWAS",
+            KnownCommand::KILL => b"KILL",
+            KnownCommand::PING => b"PING",
+            KnownCommand::PONG => b"PONG",
+            KnownCommand::ERROR => b"ERROR",
+            KnownCommand::AWAY => b"AWAY",
+            KnownCommand::REHASH => b"REHASH",
+            KnownCommand::DIE => b"DIE",
+            KnownCommand::RESTART => b"RESTART",
+            KnownCommand::SUMMON => b"SUMMON",
+            KnownCommand::USERS => b"USERS",
+            KnownCommand::WALLOPS => b"WALLOPS",
+            KnownCommand::USERHOST => b"USERHOST",
+            KnownCommand::ISON => b"ISON",
+            KnownCommand::CAP => b"CAP",
+            KnownCommand::AUTHENTICATE => b"AUTHENTICATE",
+            KnownCommand::TAGMSG => b"TAGMSG",
+            KnownCommand::BATCH => b"BATCH",
+            KnownCommand::ACCOUNT => b"ACCOUNT",
+            KnownCommand::CHGHOST => b"CHGHOST",
+            KnownCommand::SETNAME => b"SETNAME",
+            KnownCommand::MONITOR => b"MONITOR",
+            KnownCommand::FAIL => b"FAIL",
+            KnownCommand::WARN => b"WARN",
+            KnownCommand::NOTE => b"NOTE",
+            KnownCommand::CHATHISTORY => b"CHATHISTORY",
+            KnownCommand::WEBIRC => b"WEBIRC",
+            KnownCommand::STARTTLS => b"STARTTLS",
+        }
+    }
 }
 
 /// Parsed IRC command.
@@ -496,11 +707,48 @@ pub enum Command<'a> {
     String(&'a [u8]),
 }
 
+/// Message tag: a key and an optional unescaped value.
+pub type Tag<'a> = (&'a [u8], Option<Cow<'a, [u8]>>);
+
+/// Tag key split into its parts, e.g. `+example.com/foo`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TagKey<'a> {
+    /// Whether the key has the `+` prefix of client-only tags.
+    pub client_only: bool,
+    /// Vendor namespace, e.g. `example.com` or `draft`.
+    pub vendor: Option<&'a [u8]>,
+    /// Name without the prefix and the vendor.
+    pub name: &'a [u8],
+}
+
+fn is_key_name(name: &[u8]) -> bool {
+    !name.is_empty() && name.iter().all(|&b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+impl<'a> TagKey<'a> {
+    /// Split a tag key. Returns `None` if the key is invalid, e.g. it's empty or the vendor isn't
+    /// a hostname.
+    pub fn parse(key: &'a [u8]) -> Option<TagKey<'a>> {
+        let (client_only, key) = match key.first() {
+            Some(&b'+') => (true, &key[1..]),
+            _ => (false, key),
+        };
+        let (vendor, name) = match key.iter().rposition(|&b| b == b'/') {
+            Some(slash) => (Some(&key[..slash]), &key[slash + 1..]),
+            None => (None, key),
+        };
+        if !is_key_name(name) || !vendor.map_or(true, |vendor| vendor.split(|&b| b == b'.').all(is_key_name)) {
+            return None;
+        }
+        Some(TagKey { client_only, vendor, name })
+    }
+}
+
 /// Parsed IRC message.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Message<'a> {
     /// [IRCv3.2 message tags](http://ircv3.net/specs/core/message-tags-3.2.html)
-    pub tags: Vec<(&'a [u8], Option<Cow<'a, [u8]>>)>,
+    pub tags: Vec<Tag<'a>>,
     /// Message source.
     pub prefix: Prefix<'a>,
     /// Command.
@@ -509,35 +757,428 @@ pub struct Message<'a> {
     pub params: Vec<&'a [u8]>,
 }
 
-fn unescape_value(value: &[u8]) -> Cow<[u8]> {
-    const ESCAPE_SEQUENCES: [(&'static [u8], u8); 5] =
-        [(b"\\:", b';'), (b"\\s", b' '), (b"\\\\", b'\\'), (b"\\r", b'\r'), (b"\\n", b'\n')];
-
-    let mut value = Cow::Borrowed(value);
-    for &(pattern, replacement) in &ESCAPE_SEQUENCES {
-        let mut start = 0;
-        loop {
-            match twoway::find_bytes(&value[start..], pattern) {
-                Some(idx) => {
-                    let idx = start + idx;
-                    let value = value.to_mut();
-                    drop(value.drain(idx + 1..idx + pattern.len()));
-                    value[idx] = replacement;
-                    start = idx + 1;
+/// Unescape a tag value according to the message tags spec. A backslash before any other
+/// character is dropped and so is a trailing backslash, e.g. `\b` becomes `b`.
+pub fn unescape_value(value: &[u8]) -> Cow<'_, [u8]> {
+    if !value.contains(&b'\\') {
+        return Cow::Borrowed(value);
+    }
+
+    let mut unescaped = Vec::with_capacity(value.len());
+    let mut bytes = value.iter();
+    while let Some(&b) = bytes.next() {
+        if b != b'\\' {
+            unescaped.push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(&b':') => unescaped.push(b';'),
+            Some(&b's') => unescaped.push(b' '),
+            Some(&b'r') => unescaped.push(b'\r'),
+            Some(&b'n') => unescaped.push(b'\n'),
+            Some(&b) => unescaped.push(b),
+            None => (),
+        }
+    }
+    Cow::Owned(unescaped)
+}
+
+/// Escape a tag value. The inverse of `unescape_value`.
+pub fn escape_value(value: &[u8]) -> Cow<'_, [u8]> {
+    if !value.iter().any(|&b| b == b';' || b == b' ' || b == b'\\' || b == b'\r' || b == b'\n') {
+        return Cow::Borrowed(value);
+    }
+
+    let mut escaped = Vec::with_capacity(value.len() + 8);
+    for &b in value {
+        match b {
+            b';' => escaped.extend_from_slice(b"\\:"),
+            b' ' => escaped.extend_from_slice(b"\\s"),
+            b'\\' => escaped.extend_from_slice(b"\\\\"),
+            b'\r' => escaped.extend_from_slice(b"\\r"),
+            b'\n' => escaped.extend_from_slice(b"\\n"),
+            b => escaped.push(b),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+fn invalid_input(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+impl<'a> Prefix<'a> {
+    fn check(&self) -> io::Result<()> {
+        let parts = match *self {
+            Prefix::Server(host) => [Some(host), None, None],
+            Prefix::User { nick, user, host } => [Some(nick), user, host],
+            Prefix::Implicit => return Ok(()),
+        };
+        if parts.iter().flatten().any(|part| part.iter().any(|&b| b == b' ' || b == b'\0' || b == b'\r' || b == b'\n')) {
+            return Err(invalid_input("prefix contains a space, NUL, CR or LF"));
+        }
+        // The nick or server name ends at the first `!` or `@` when parsed.
+        let name = parts[0].unwrap_or(b"");
+        if name.is_empty() || name.iter().any(|&b| b == b'!' || b == b'@') {
+            return Err(invalid_input("nick or server name is empty or contains '!' or '@'"));
+        }
+        if parts[1].is_some_and(|user| user.contains(&b'@')) {
+            return Err(invalid_input("user contains '@'"));
+        }
+        Ok(())
+    }
+
+    /// Write the prefix, including the leading `:` and the trailing space. Writes nothing for
+    /// `Prefix::Implicit`. Fails with `ErrorKind::InvalidInput` if a part of the prefix contains
+    /// a space, NUL, CR or LF, the nick or server name is empty or contains `!` or `@`, or the
+    /// user contains `@`.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.check()?;
+        match *self {
+            Prefix::Server(host) => {
+                w.write_all(b":")?;
+                w.write_all(host)?;
+            }
+            Prefix::User { nick, user, host } => {
+                w.write_all(b":")?;
+                w.write_all(nick)?;
+                if let Some(user) = user {
+                    w.write_all(b"!")?;
+                    w.write_all(user)?;
+                }
+                if let Some(host) = host {
+                    w.write_all(b"@")?;
+                    w.write_all(host)?;
                 }
-                None => break,
             }
+            Prefix::Implicit => return Ok(()),
+        }
+        w.write_all(b" ")
+    }
+
+    /// Host of a `Prefix::User` as an IP address. Returns `None` for other prefixes and for hosts
+    /// that are hostnames or cloaks rather than valid IPv4 or IPv6 addresses.
+    pub fn ip_addr(&self) -> Option<IpAddr> {
+        match *self {
+            Prefix::User { host: Some(host), .. } => std::str::from_utf8(host).ok()?.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Command<'a> {
+    fn check(&self) -> io::Result<()> {
+        match *self {
+            Command::Numeric(numeric) if numeric > 999 => Err(invalid_input("numeric is longer than three digits")),
+            Command::String(cmd) if cmd.is_empty() || !cmd.iter().all(u8::is_ascii_alphabetic) => {
+                Err(invalid_input("command is empty or not alphabetic"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Write the command. Numerics are written as three zero-padded digits. Fails with
+    /// `ErrorKind::InvalidInput` if a numeric is above 999 or a string command is empty or not
+    /// alphabetic.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.check()?;
+        match *self {
+            Command::Reply(reply) => write!(w, "{:03}", reply as u16),
+            Command::Error(error) => write!(w, "{:03}", error as u16),
+            Command::Numeric(numeric) => write!(w, "{:03}", numeric),
+            Command::Command(cmd) => w.write_all(cmd.as_bytes()),
+            Command::String(cmd) => w.write_all(cmd),
+        }
+    }
+}
+
+impl<'a> Message<'a> {
+    /// Value of the tag `key`. Tags without a value yield an empty slice. If the tag is present
+    /// more than once the last one wins.
+    pub fn tag(&self, key: &[u8]) -> Option<&[u8]> {
+        self.tags.iter()
+            .rev()
+            .find(|&&(k, _)| k == key)
+            .map(|(_, value)| value.as_ref().map_or(&b""[..], |value| &value[..]))
+    }
+
+    /// Time the message was sent according to the
+    /// [`time`](https://ircv3.net/specs/extensions/server-time) tag.
+    pub fn server_time(&self) -> Option<time::Timestamp> {
+        self.tag(b"time").and_then(time::Timestamp::parse_rfc3339)
+    }
+
+    /// Time the message was sent according to Twitch's `tmi-sent-ts` tag.
+    pub fn tmi_sent_ts(&self) -> Option<time::Timestamp> {
+        let millis = std::str::from_utf8(self.tag(b"tmi-sent-ts")?).ok()?.parse().ok()?;
+        Some(time::Timestamp::from_unix_millis(millis))
+    }
+
+    /// Unique message ID from the [`msgid`](https://ircv3.net/specs/extensions/message-ids) tag.
+    pub fn msgid(&self) -> Option<&[u8]> {
+        self.tag(b"msgid")
+    }
+
+    /// Account of the sender from the [`account`](https://ircv3.net/specs/extensions/account-tag)
+    /// tag.
+    pub fn account(&self) -> Option<&[u8]> {
+        self.tag(b"account")
+    }
+
+    /// Label of the command this message responds to from the
+    /// [`label`](https://ircv3.net/specs/extensions/labeled-response) tag.
+    pub fn label(&self) -> Option<&[u8]> {
+        self.tag(b"label")
+    }
+
+    /// Reference tag of the [batch](https://ircv3.net/specs/extensions/batch) the message is part
+    /// of.
+    pub fn batch(&self) -> Option<&[u8]> {
+        self.tag(b"batch")
+    }
+
+    /// Whether the sender is marked as a [bot](https://ircv3.net/specs/extensions/bot-mode).
+    pub fn bot(&self) -> bool {
+        self.tag(b"bot").is_some() || self.tag(b"draft/bot").is_some()
+    }
+
+    /// Write the message in wire format, terminated by CRLF.
+    ///
+    /// Tag values are escaped and the last parameter is prefixed with `:` only if it is empty,
+    /// starts with `:` or contains a space. Fails with `ErrorKind::InvalidInput` if the message
+    /// can't be represented, e.g. a parameter other than the last one contains a space, a
+    /// parameter contains a CR, LF or NUL, a tag key is invalid or a tag value contains a NUL.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.prefix.check()?;
+        self.command.check()?;
+        for (i, param) in self.params.iter().enumerate() {
+            if param.iter().any(|&b| b == b'\0' || b == b'\r' || b == b'\n') {
+                return Err(invalid_input("parameter contains a NUL, CR or LF"));
+            }
+            if i + 1 < self.params.len() && (param.is_empty() || param[0] == b':' || param.contains(&b' ')) {
+                return Err(invalid_input("only the last parameter can be empty, start with ':' or contain a space"));
+            }
+        }
+
+        if self.tags.iter().any(|&(key, _)| TagKey::parse(key).is_none()) {
+            return Err(invalid_input("invalid tag key"));
+        }
+        if self.tags.iter().any(|(_, value)| value.as_ref().is_some_and(|value| value.contains(&b'\0'))) {
+            return Err(invalid_input("tag value contains a NUL"));
+        }
+
+        if !self.tags.is_empty() {
+            w.write_all(b"@")?;
+            for (i, &(key, ref value)) in self.tags.iter().enumerate() {
+                if i > 0 {
+                    w.write_all(b";")?;
+                }
+                w.write_all(key)?;
+                if let Some(ref value) = *value {
+                    w.write_all(b"=")?;
+                    w.write_all(&escape_value(value))?;
+                }
+            }
+            w.write_all(b" ")?;
+        }
+
+        self.prefix.write_to(w)?;
+        self.command.write_to(w)?;
+
+        if let Some((last, middle)) = self.params.split_last() {
+            for param in middle {
+                w.write_all(b" ")?;
+                w.write_all(param)?;
+            }
+            w.write_all(b" ")?;
+            if last.is_empty() || last[0] == b':' || last.contains(&b' ') {
+                w.write_all(b":")?;
+            }
+            w.write_all(last)?;
+        }
+
+        w.write_all(b"\r\n")
+    }
+
+    /// Serialize the message into a new buffer. See `write_to`.
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.write_to(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Owned counterpart of `Prefix`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OwnedPrefix {
+    /// Message was sent by a server.
+    Server(Vec<u8>),
+    /// Message was sent by a user.
+    User {
+        /// User's nickname.
+        nick: Vec<u8>,
+        /// User's username.
+        user: Option<Vec<u8>>,
+        /// User's hostname, IP address or cloak.
+        host: Option<Vec<u8>>,
+    },
+    /// Prefix was missing.
+    Implicit,
+}
+
+impl OwnedPrefix {
+    /// Borrow as a `Prefix`.
+    pub fn borrow(&self) -> Prefix<'_> {
+        match *self {
+            OwnedPrefix::Server(ref host) => Prefix::Server(host),
+            OwnedPrefix::User { ref nick, ref user, ref host } => Prefix::User {
+                nick,
+                user: user.as_ref().map(|user| &user[..]),
+                host: host.as_ref().map(|host| &host[..]),
+            },
+            OwnedPrefix::Implicit => Prefix::Implicit,
+        }
+    }
+}
+
+impl<'a> Prefix<'a> {
+    /// Copy the borrowed data into an `OwnedPrefix`.
+    pub fn into_owned(self) -> OwnedPrefix {
+        match self {
+            Prefix::Server(host) => OwnedPrefix::Server(host.to_vec()),
+            Prefix::User { nick, user, host } => OwnedPrefix::User {
+                nick: nick.to_vec(),
+                user: user.map(<[u8]>::to_vec),
+                host: host.map(<[u8]>::to_vec),
+            },
+            Prefix::Implicit => OwnedPrefix::Implicit,
+        }
+    }
+}
+
+impl<'a> From<Prefix<'a>> for OwnedPrefix {
+    fn from(prefix: Prefix<'a>) -> OwnedPrefix {
+        prefix.into_owned()
+    }
+}
+
+/// Owned counterpart of `Command`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OwnedCommand {
+    /// Numeric reply.
+    Reply(Reply),
+    /// Numeric error.
+    Error(Error),
+    /// Command.
+    Command(KnownCommand),
+    /// An unknown numeric response.
+    Numeric(u16),
+    /// An unknown string command.
+    String(Vec<u8>),
+}
+
+impl OwnedCommand {
+    /// Borrow as a `Command`.
+    pub fn borrow(&self) -> Command<'_> {
+        match *self {
+            OwnedCommand::Reply(reply) => Command::Reply(reply),
+            OwnedCommand::Error(error) => Command::Error(error),
+            OwnedCommand::Command(cmd) => Command::Command(cmd),
+            OwnedCommand::Numeric(numeric) => Command::Numeric(numeric),
+            OwnedCommand::String(ref cmd) => Command::String(cmd),
         }
     }
+}
+
+impl<'a> Command<'a> {
+    /// Copy the borrowed data into an `OwnedCommand`.
+    pub fn into_owned(self) -> OwnedCommand {
+        match self {
+            Command::Reply(reply) => OwnedCommand::Reply(reply),
+            Command::Error(error) => OwnedCommand::Error(error),
+            Command::Command(cmd) => OwnedCommand::Command(cmd),
+            Command::Numeric(numeric) => OwnedCommand::Numeric(numeric),
+            Command::String(cmd) => OwnedCommand::String(cmd.to_vec()),
+        }
+    }
+}
 
-    value
+impl<'a> From<Command<'a>> for OwnedCommand {
+    fn from(command: Command<'a>) -> OwnedCommand {
+        command.into_owned()
+    }
 }
 
-named!(host<&[u8]>,
-    alt!(hostname | hostaddr)
+/// Owned counterpart of `Message` that doesn't borrow from the input buffer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedMessage {
+    /// [IRCv3.2 message tags](http://ircv3.net/specs/core/message-tags-3.2.html)
+    pub tags: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    /// Message source.
+    pub prefix: OwnedPrefix,
+    /// Command.
+    pub command: OwnedCommand,
+    /// Command parameters.
+    pub params: Vec<Vec<u8>>,
+}
+
+impl OwnedMessage {
+    /// Borrow as a `Message`.
+    pub fn borrow(&self) -> Message<'_> {
+        Message {
+            tags: self.tags.iter()
+                .map(|(key, value)| (&key[..], value.as_ref().map(|value| Cow::Borrowed(&value[..]))))
+                .collect(),
+            prefix: self.prefix.borrow(),
+            command: self.command.borrow(),
+            params: self.params.iter().map(|param| &param[..]).collect(),
+        }
+    }
+
+    /// Write the message in wire format. See `Message::write_to`.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.borrow().write_to(w)
+    }
+
+    /// Serialize the message into a new buffer. See `Message::write_to`.
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        self.borrow().to_bytes()
+    }
+}
+
+impl<'a> Message<'a> {
+    /// Copy the borrowed data into an `OwnedMessage`.
+    pub fn into_owned(self) -> OwnedMessage {
+        OwnedMessage {
+            tags: self.tags.into_iter()
+                .map(|(key, value)| (key.to_vec(), value.map(Cow::into_owned)))
+                .collect(),
+            prefix: self.prefix.into_owned(),
+            command: self.command.into_owned(),
+            params: self.params.into_iter().map(<[u8]>::to_vec).collect(),
+        }
+    }
+}
+
+impl<'a> From<Message<'a>> for OwnedMessage {
+    fn from(message: Message<'a>) -> OwnedMessage {
+        message.into_owned()
+    }
+}
+
+impl<'a> From<&'a OwnedMessage> for Message<'a> {
+    fn from(message: &'a OwnedMessage) -> Message<'a> {
+        message.borrow()
+    }
+}
+
+// Networks send IP addresses and cloaks such as `user/foo` or `gateway/web/irccloud.com/x-abc`
+// as well as hostnames, so any printable ASCII up to the space is accepted.
+named!(host<&'a [u8]>,
+    take_while1!(call!(|b: u8| b.is_ascii_graphic()))
 );
 
-named!(hostname<&[u8]>,
+named!(hostname<&'a [u8]>,
     recognize!(
         separated_nonempty_list!(
             tag!(b"."),
@@ -545,42 +1186,25 @@ named!(hostname<&[u8]>,
     )
 );
 
-named!(hostaddr<&[u8]>,
-    alt!(ip4addr | ip6addr)
-);
-
-named!(ip4addr<&[u8]>,
-    is_a!(&b"0123456789."[..])
-);
-
-named!(ip6addr<&[u8]>,
-    is_a!(&b"0123456789abcdefABCDEF:."[..])
-);
-
-named!(tags<Vec<(&[u8], Option<Cow<[u8]> >)> >,
+named!(tags<Vec<Tag<'a>> >,
     do_parse!(
         tag!(b"@") >>
         tags: separated_nonempty_list!(tag!(b";"), tag) >>
+        // Some servers end the tags with a `;`.
+        opt!(tag!(b";")) >>
         tag!(b" ") >>
         (tags)
     )
 );
 
-named!(tag<(&[u8], Option<Cow<[u8]>>)>,
+/// Accept `key` if it's a valid tag key.
+fn tag_key(key: &[u8]) -> Option<&[u8]> {
+    TagKey::parse(key).map(|_| key)
+}
+
+named!(tag<Tag<'a>>,
     do_parse!(
-        key: recognize!(
-            do_parse!(
-                opt!(
-                    do_parse!(
-                        host >>
-                        tag!(b"/") >>
-                        ()
-                    )
-                ) >>
-                take_while!(call!(|b| nom::is_alphanumeric(b) || b == b'-')) >>
-                ()
-            )
-        ) >>
+        key: map_opt!(is_not!(&b"=; \0\r\n"[..]), tag_key) >>
         value: opt!(
             do_parse!(
                 tag!(b"=") >>
@@ -594,25 +1218,40 @@ named!(tag<(&[u8], Option<Cow<[u8]>>)>,
 
 /// Checks whether `b` is any of ``[\]`_^{|}``
 fn is_special(b: u8) -> bool {
-    0x5B <= b && b <= 0x60 || 0x7B <= b && b <= 0x7D
+    (0x5B..=0x60).contains(&b) || (0x7B..=0x7D).contains(&b)
 }
 
-named!(nickname<&[u8]>,
-    take_while!(call!(|b| nom::is_alphanumeric(b) || is_special(b)))
+named!(nickname<&'a [u8]>,
+    take_while1!(call!(|b| nom::is_alphanumeric(b) || is_special(b) || b == b'-'))
 );
 
-named!(user<&[u8]>,
+named!(user<&'a [u8]>,
     is_not!(&b"\0\r\n @"[..])
 );
 
-named!(prefix<Prefix>,
+/// Classify a prefix without `!` or `@`. Returns `None` if it's empty.
+fn bare_name(name: &[u8]) -> Option<Prefix<'_>> {
+    if name.is_empty() {
+        None
+    } else if name.contains(&b'.') {
+        Some(Prefix::Server(name))
+    } else {
+        Some(Prefix::User {
+            nick: name,
+            user: None,
+            host: None,
+        })
+    }
+}
+
+named!(prefix<Prefix<'a>>,
     do_parse!(
         tag!(b":") >>
         prefix: alt!(
             do_parse!(
-                host: hostname >>
+                prefix: map_opt!(hostname, bare_name) >>
                 tag!(b" ") >>
-                (Prefix::Server(host))
+                (prefix)
             ) |
             do_parse!(
                 nick: nickname >>
@@ -632,9 +1271,9 @@ named!(prefix<Prefix>,
                 ) >>
                 tag!(b" ") >>
                 (Prefix::User {
-                    nick: nick,
-                    user: user,
-                    host: host,
+                    nick,
+                    user,
+                    host,
                 })
             )
         ) >>
@@ -642,20 +1281,24 @@ named!(prefix<Prefix>,
     )
 );
 
-fn parse_numeric_response(response: &[u8]) -> Command {
-    let response = ((response[0] - b'0') as u16) * 100 + ((response[1] - b'0') as u16) * 10 + ((response[2] - b'0') as u16);
+/// Parse a numeric. Returns `None` unless `response` is exactly three digits.
+fn parse_numeric_response(response: &[u8]) -> Option<Command<'_>> {
+    if response.len() != 3 || !response.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let response = response.iter().fold(0, |response, &b| response * 10 + u16::from(b - b'0'));
     if let Some(reply) = Reply::from(response) {
-        return Command::Reply(reply);
+        return Some(Command::Reply(reply));
     }
 
     if let Some(error) = Error::from(response) {
-        return Command::Error(error);
+        return Some(Command::Error(error));
     }
 
-    Command::Numeric(response)
+    Some(Command::Numeric(response))
 }
 
-fn parse_string_command(cmd: &[u8]) -> Command {
+fn parse_string_command(cmd: &[u8]) -> Command<'_> {
     if let Some(cmd) = KnownCommand::from(cmd) {
         return Command::Command(cmd);
     }
@@ -663,14 +1306,15 @@ fn parse_string_command(cmd: &[u8]) -> Command {
     Command::String(cmd)
 }
 
-named!(command<Command>,
+named!(command<Command<'a>>,
     alt!(
-        map!(digit, parse_numeric_response) |
+        // The numeric might continue if the input ends after the digits.
+        map_opt!(terminated!(digit, peek!(take!(1))), parse_numeric_response) |
         map!(alpha, parse_string_command)
     )
 );
 
-named!(params<Vec<&[u8]> >,
+named!(params<Vec<&'a [u8]> >,
     do_parse!(
         params: opt!(
             do_parse!(
@@ -682,8 +1326,8 @@ named!(params<Vec<&[u8]> >,
         trailing: opt!(
             do_parse!(
                 tag!(b" :") >>
-                trailing: trailing >>
-                (trailing)
+                trailing: opt!(trailing) >>
+                (trailing.unwrap_or(b""))
             )
         ) >>
         ({
@@ -694,7 +1338,34 @@ named!(params<Vec<&[u8]> >,
     )
 );
 
-named!(middle<&[u8]>,
+// Like `params` but accepts repeated spaces between parameters and trailing spaces.
+named!(params_lenient<Vec<&'a [u8]> >,
+    do_parse!(
+        params: many0!(
+            do_parse!(
+                is_a!(&b" "[..]) >>
+                param: middle >>
+                (param)
+            )
+        ) >>
+        trailing: opt!(
+            do_parse!(
+                is_a!(&b" "[..]) >>
+                tag!(b":") >>
+                trailing: opt!(trailing) >>
+                (trailing.unwrap_or(b""))
+            )
+        ) >>
+        opt!(is_a!(&b" "[..])) >>
+        ({
+            let mut params = params;
+            params.extend(trailing);
+            params
+        })
+    )
+);
+
+named!(middle<&'a [u8]>,
     recognize!(
         do_parse!(
             is_not!(&b"\0\r\n :"[..]) >>
@@ -704,32 +1375,280 @@ named!(middle<&[u8]>,
     )
 );
 
-named!(trailing<&[u8]>,
+named!(trailing<&'a [u8]>,
     is_not!(&b"\0\r\n"[..])
 );
 
-named_attr!(#[doc="Parse an IRC message."], pub message<Message>,
-    do_parse!(
-        tags: opt!(tags) >>
-        prefix: opt!(prefix) >>
-        command: command >>
-        params: params >>
-        tag!(b"\r\n") >>
-        (Message {
-            tags: tags.unwrap_or_else(Vec::new),
-            prefix: prefix.unwrap_or(Prefix::Implicit),
-            command: command,
-            params: params,
-        })
-    )
-);
+/// Part of the message that failed to parse.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// Invalid message tags.
+    Tags,
+    /// Invalid prefix.
+    Prefix,
+    /// Invalid command.
+    Command,
+    /// Invalid command parameters.
+    Params,
+    /// The message isn't terminated by CRLF.
+    MissingCrlf,
+    /// The message or its tags exceed the length limit.
+    TooLong,
+}
+
+impl ParseErrorKind {
+    fn description(&self) -> &'static str {
+        match *self {
+            ParseErrorKind::Tags => "invalid message tags",
+            ParseErrorKind::Prefix => "invalid prefix",
+            ParseErrorKind::Command => "invalid command",
+            ParseErrorKind::Params => "invalid parameters",
+            ParseErrorKind::MissingCrlf => "message not terminated by CRLF",
+            ParseErrorKind::TooLong => "message too long",
+        }
+    }
+}
+
+/// Error returned by `parse_message`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// Part of the message that failed to parse.
+    pub kind: ParseErrorKind,
+    /// Byte offset into the input where parsing failed. For tags this is the start of the
+    /// invalid tag or the unexpected byte after the last valid one.
+    pub offset: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.kind.description(), self.offset)
+    }
+}
+
+impl error::Error for ParseError {}
+
+named!(crlf, tag!(b"\r\n"));
+
+named!(line_end_lenient, alt!(tag!(b"\r\n") | tag!(b"\n")));
+
+/// Maximum length of a message without tags, including the source added by the server and the
+/// trailing CRLF.
+pub const MAX_LINE_LEN: usize = 512;
+/// Maximum length of the tags of a message, including the leading `@` and the trailing space.
+pub const MAX_TAGS_LEN: usize = 8191;
+
+/// Options for `parse_message_with` and `message_with`.
+///
+/// The default accepts messages that follow the grammar exactly but doesn't enforce any limits.
+/// NUL, CR and LF are never accepted inside a message.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParserConfig {
+    /// Accept a bare LF as the line terminator.
+    pub allow_bare_lf: bool,
+    /// Accept repeated spaces between the parts of the message and trailing spaces before the
+    /// line terminator.
+    pub collapse_spaces: bool,
+    /// Match known commands case-insensitively, e.g. `privmsg` parses as `PRIVMSG`.
+    pub case_insensitive_commands: bool,
+    /// Maximum number of parameters.
+    pub max_params: Option<usize>,
+    /// Maximum length of the message without tags, including the line terminator.
+    pub max_line_len: Option<usize>,
+    /// Maximum length of the tags, including the leading `@` and the trailing space.
+    pub max_tags_len: Option<usize>,
+}
+
+impl ParserConfig {
+    /// Reject messages that don't conform to the protocol: more than 15 parameters, lines longer
+    /// than 512 bytes or tags longer than 8191 bytes.
+    pub fn strict() -> ParserConfig {
+        ParserConfig {
+            max_params: Some(15),
+            max_line_len: Some(MAX_LINE_LEN),
+            max_tags_len: Some(MAX_TAGS_LEN),
+            ..ParserConfig::default()
+        }
+    }
+
+    /// Accept common deviations from the protocol: bare LF line terminators, repeated and
+    /// trailing spaces and lowercase commands.
+    pub fn lenient() -> ParserConfig {
+        ParserConfig {
+            allow_bare_lf: true,
+            collapse_spaces: true,
+            case_insensitive_commands: true,
+            ..ParserConfig::default()
+        }
+    }
+
+    /// Check the length limits before parsing so that over-long lines are rejected even when
+    /// they're incomplete.
+    fn check_len(&self, input: &[u8]) -> Result<(), ParseError> {
+        let too_long = |offset| Err(ParseError { kind: ParseErrorKind::TooLong, offset });
+
+        let tags_len = if input.first() == Some(&b'@') {
+            let len = input.iter().position(|&b| b == b' ').map_or(input.len(), |space| space + 1);
+            if self.max_tags_len.is_some_and(|max| len > max) {
+                return too_long(0);
+            }
+            len
+        } else {
+            0
+        };
+
+        let line = &input[tags_len..];
+        let len = line.iter().position(|&b| b == b'\n').map_or(line.len(), |lf| lf + 1);
+        if self.max_line_len.is_some_and(|max| len > max) {
+            return too_long(tags_len);
+        }
+        Ok(())
+    }
+}
+
+/// Skip the spaces at the start of `input`.
+fn skip_spaces(input: &[u8]) -> &[u8] {
+    let spaces = input.iter().take_while(|&&b| b == b' ').count();
+    &input[spaces..]
+}
+
+/// Offset of the first byte in `input` that doesn't fit the tags, i.e. the start of the first
+/// invalid tag or the byte following the last valid one.
+fn tags_error_offset(input: &[u8]) -> usize {
+    let mut rest = &input[1..];
+    while let IResult::Done(tail, _) = tag(rest) {
+        rest = tail;
+        match rest.first() {
+            Some(&b';') => rest = &rest[1..],
+            _ => break,
+        }
+    }
+    input.len() - rest.len()
+}
+
+/// Offset of the first byte in `input` that doesn't fit the prefix. Whichever of the server and
+/// the user form gets further decides.
+fn prefix_error_offset(input: &[u8]) -> usize {
+    let name = &input[1..];
+    let server = match hostname(name) {
+        IResult::Done(tail, _) => tail,
+        _ => name,
+    };
+
+    let offset = |rest: &[u8]| input.len() - server.len().min(rest.len());
+    let mut user = match nickname(name) {
+        IResult::Done(tail, _) => tail,
+        _ => return offset(name),
+    };
+    if user.first() == Some(&b'!') {
+        user = &user[1..];
+        match self::user(user) {
+            IResult::Done(tail, _) => user = tail,
+            _ => return offset(user),
+        }
+    }
+    if user.first() == Some(&b'@') {
+        user = &user[1..];
+        if let IResult::Done(tail, _) = host(user) {
+            user = tail;
+        }
+    }
+    offset(user)
+}
 
 /// Parse a message. Returns `Some` with the parsed message and its length if the message is complete or `None` if the message is incomplete.
-pub fn parse_message(input: &[u8]) -> Result<Option<(Message, usize)>, ()> {
-    match message(input) {
-        IResult::Done(tail, msg) => Ok(Some((msg, input.len() - tail.len()))),
-        IResult::Incomplete(_) => Ok(None),
-        IResult::Error(_) => Err(()),
+pub fn parse_message(input: &[u8]) -> Result<Option<(Message<'_>, usize)>, ParseError> {
+    parse_message_with(input, &ParserConfig::default())
+}
+
+/// Parse a message according to `config`. See `parse_message`.
+pub fn parse_message_with<'a>(input: &'a [u8], config: &ParserConfig) -> Result<Option<(Message<'a>, usize)>, ParseError> {
+    config.check_len(input)?;
+
+    let mut rest = input;
+
+    macro_rules! part {
+        ($parser:ident, $kind:expr) => {
+            part!($parser, $kind, |_| 0)
+        };
+        ($parser:ident, $kind:expr, $error_offset:expr) => {
+            match $parser(rest) {
+                IResult::Done(tail, value) => {
+                    rest = tail;
+                    value
+                }
+                IResult::Incomplete(_) => return Ok(None),
+                IResult::Error(_) => return Err(ParseError {
+                    kind: $kind,
+                    offset: input.len() - rest.len() + $error_offset(rest),
+                }),
+            }
+        };
+    }
+
+    let tags = if rest.first() == Some(&b'@') {
+        part!(tags, ParseErrorKind::Tags, tags_error_offset)
+    } else {
+        vec![]
+    };
+    if config.collapse_spaces {
+        rest = skip_spaces(rest);
+    }
+    let prefix = if rest.first() == Some(&b':') {
+        part!(prefix, ParseErrorKind::Prefix, prefix_error_offset)
+    } else {
+        Prefix::Implicit
+    };
+    if config.collapse_spaces {
+        rest = skip_spaces(rest);
+    }
+    let command = match part!(command, ParseErrorKind::Command) {
+        Command::String(cmd) if config.case_insensitive_commands => {
+            KnownCommand::from(&cmd.to_ascii_uppercase()).map_or(Command::String(cmd), Command::Command)
+        }
+        command => command,
+    };
+    let params_offset = input.len() - rest.len();
+    let params = if config.collapse_spaces {
+        part!(params_lenient, ParseErrorKind::Params)
+    } else {
+        part!(params, ParseErrorKind::Params)
+    };
+    if config.max_params.is_some_and(|max| params.len() > max) {
+        return Err(ParseError {
+            kind: ParseErrorKind::Params,
+            offset: params_offset,
+        });
+    }
+    let kind = match rest.first() {
+        Some(&b'\r') | Some(&b'\n') => ParseErrorKind::MissingCrlf,
+        _ => ParseErrorKind::Params,
+    };
+    if config.allow_bare_lf {
+        part!(line_end_lenient, kind);
+    } else {
+        part!(crlf, kind);
+    }
+
+    Ok(Some((Message {
+        tags,
+        prefix,
+        command,
+        params,
+    }, input.len() - rest.len())))
+}
+
+/// Parse an IRC message.
+pub fn message(input: &[u8]) -> IResult<&[u8], Message<'_>> {
+    message_with(input, &ParserConfig::default())
+}
+
+/// Parse an IRC message according to `config`. Errors don't carry any details, use
+/// `parse_message_with` for those.
+pub fn message_with<'a>(input: &'a [u8], config: &ParserConfig) -> IResult<&'a [u8], Message<'a>> {
+    match parse_message_with(input, config) {
+        Ok(Some((message, len))) => IResult::Done(&input[len..], message),
+        Ok(None) => IResult::Incomplete(nom::Needed::Unknown),
+        Err(_) => IResult::Error(nom::ErrorKind::Custom(0)),
     }
 }
 
@@ -859,13 +1778,13 @@ fn twitch_examples() {
     assert_eq!(message(b"CAP REQ :twitch.tv/membership\r\n"), nom::IResult::Done(&b""[..], Message {
         tags: vec![],
         prefix: Prefix::Implicit,
-        command: Command::String(b"CAP"),
+        command: Command::Command(KnownCommand::CAP),
         params: vec![b"REQ", b"twitch.tv/membership"],
     }));
     assert_eq!(message(b":tmi.twitch.tv CAP * ACK :twitch.tv/membership\r\n"), nom::IResult::Done(&b""[..], Message {
         tags: vec![],
         prefix: Prefix::Server(b"tmi.twitch.tv"),
-        command: Command::String(b"CAP"),
+        command: Command::Command(KnownCommand::CAP),
         params: vec![b"*", b"ACK", b"twitch.tv/membership"],
     }));
     assert_eq!(message(b":twitch_username.tmi.twitch.tv 353 twitch_username = #channel :twitch_username user2 user3\r\n"), nom::IResult::Done(&b""[..], Message {
@@ -908,26 +1827,34 @@ fn twitch_examples() {
     }));
     assert_eq!(message(b":jtv MODE #channel +o operator_user\r\n"), nom::IResult::Done(&b""[..], Message {
         tags: vec![],
-        prefix: Prefix::Server(b"jtv"),
+        prefix: Prefix::User {
+            nick: b"jtv",
+            user: None,
+            host: None,
+        },
         command: Command::Command(KnownCommand::MODE),
         params: vec![b"#channel", b"+o", b"operator_user"],
     }));
     assert_eq!(message(b":jtv MODE #channel -o operator_user\r\n"), nom::IResult::Done(&b""[..], Message {
         tags: vec![],
-        prefix: Prefix::Server(b"jtv"),
+        prefix: Prefix::User {
+            nick: b"jtv",
+            user: None,
+            host: None,
+        },
         command: Command::Command(KnownCommand::MODE),
         params: vec![b"#channel", b"-o", b"operator_user"],
     }));
     assert_eq!(message(b"CAP REQ :twitch.tv/commands\r\n"), nom::IResult::Done(&b""[..], Message {
         tags: vec![],
         prefix: Prefix::Implicit,
-        command: Command::String(b"CAP"),
+        command: Command::Command(KnownCommand::CAP),
         params: vec![b"REQ", b"twitch.tv/commands"],
     }));
     assert_eq!(message(b":tmi.twitch.tv CAP * ACK :twitch.tv/commands\r\n"), nom::IResult::Done(&b""[..], Message {
         tags: vec![],
         prefix: Prefix::Server(b"tmi.twitch.tv"),
-        command: Command::String(b"CAP"),
+        command: Command::Command(KnownCommand::CAP),
         params: vec![b"*", b"ACK", b"twitch.tv/commands"],
     }));
     assert_eq!(message(b"@msg-id=slow_off :tmi.twitch.tv NOTICE #channel :This room is no longer in slow mode.\r\n"), nom::IResult::Done(&b""[..], Message {
@@ -983,13 +1910,13 @@ fn twitch_examples() {
     assert_eq!(message(b"CAP REQ :twitch.tv/tags\r\n"), nom::IResult::Done(&b""[..], Message {
         tags: vec![],
         prefix: Prefix::Implicit,
-        command: Command::String(b"CAP"),
+        command: Command::Command(KnownCommand::CAP),
         params: vec![b"REQ", b"twitch.tv/tags"],
     }));
     assert_eq!(message(b":tmi.twitch.tv CAP * ACK :twitch.tv/tags\r\n"), nom::IResult::Done(&b""[..], Message {
         tags: vec![],
         prefix: Prefix::Server(b"tmi.twitch.tv"),
-        command: Command::String(b"CAP"),
+        command: Command::Command(KnownCommand::CAP),
         params: vec![b"*", b"ACK", b"twitch.tv/tags"],
     }));
     assert_eq!(message(b"@badges=global_mod/1,turbo/1;color=#0D4200;display-name=TWITCH_UserNaME;emotes=25:0-4,12-16/1902:6-10;mod=0;room-id=1337;subscriber=0;turbo=1;user-id=1337;user-type=global_mod :twitch_username!twitch_username@twitch_username.tmi.twitch.tv PRIVMSG #channel :Kappa Keepo Kappa\r\n"), nom::IResult::Done(&b""[..], Message {
@@ -1213,8 +2140,450 @@ fn test_unescape_value() {
     assert_eq!(unescape_value(b"\\:\\s\\\\\\r\\n"), &b"; \\\r\n"[..]);
     assert_eq!(unescape_value(b"\\s\\s\\s\\s\\s"), &b"     "[..]);
 
+    // `\\` is unescaped before the following character is looked at.
+    assert_eq!(unescape_value(b"\\\\s"), &b"\\s"[..]);
+    assert_eq!(unescape_value(b"a\\\\\\:b"), &b"a\\;b"[..]);
+    // Unknown escapes and a trailing backslash drop the backslash.
+    assert_eq!(unescape_value(b"\\b"), &b"b"[..]);
+    assert_eq!(unescape_value(b"ab\\"), &b"ab"[..]);
+    assert_eq!(unescape_value(b"\\"), &b""[..]);
+    assert_eq!(unescape_value(b"\\\\\\"), &b"\\"[..]);
+
     match unescape_value(b"no-escape-sequences") {
         Cow::Borrowed(b"no-escape-sequences") => (),
         e => panic!("Value with no escape sequences has changed: {:?}", e),
     }
 }
+
+#[test]
+fn test_escape_value() {
+    assert_eq!(escape_value(b"; \\\r\n"), &b"\\:\\s\\\\\\r\\n"[..]);
+    assert_eq!(unescape_value(&escape_value(b"a;b c\\d\r\ne")), &b"a;b c\\d\r\ne"[..]);
+    assert_eq!(unescape_value(&escape_value(b"\\s\\\\:")), &b"\\s\\\\:"[..]);
+
+    match escape_value(b"no-escape-sequences") {
+        Cow::Borrowed(b"no-escape-sequences") => (),
+        e => panic!("Value with nothing to escape has changed: {:?}", e),
+    }
+}
+
+#[test]
+fn test_write_message() {
+    let lines: &[&[u8]] = &[
+        b"PASS oauth:twitch_oauth_token\r\n",
+        b":tmi.twitch.tv 001 twitch_username :Welcome, GLHF!\r\n",
+        b":tmi.twitch.tv 004 twitch_username -\r\n",
+        b":twitch_username!twitch_username@twitch_username.tmi.twitch.tv JOIN #channel\r\n",
+        b":jtv MODE #channel +o operator_user\r\n",
+        b"@aaa=bbb;ccc;example.com/ddd=eee :nick!ident@host.com PRIVMSG me :Hello there\r\n",
+        b"@ban-duration=1;ban-reason=Follow\\sthe\\srules :tmi.twitch.tv CLEARCHAT #channel target_username\r\n",
+        b"@broadcaster-lang=;r9k=0 :tmi.twitch.tv ROOMSTATE #channel\r\n",
+        b"PRIVMSG #channel ::-)\r\n",
+    ];
+    for &line in lines {
+        match message(line) {
+            IResult::Done(_, msg) => assert_eq!(msg.to_bytes().unwrap(), line),
+            e => panic!("Failed to parse {:?}: {:?}", line, e),
+        }
+    }
+
+    // Unnecessary ':' is dropped.
+    match message(b"CAP REQ :twitch.tv/membership\r\n") {
+        IResult::Done(_, msg) => assert_eq!(msg.to_bytes().unwrap(), &b"CAP REQ twitch.tv/membership\r\n"[..]),
+        e => panic!("{:?}", e),
+    }
+
+    // Numerics are zero-padded.
+    let msg = Message {
+        tags: vec![],
+        prefix: Prefix::Server(b"irc.example.com"),
+        command: Command::Numeric(42),
+        params: vec![b"nick"],
+    };
+    assert_eq!(msg.to_bytes().unwrap(), &b":irc.example.com 042 nick\r\n"[..]);
+
+    // An empty last parameter needs a ':'.
+    let msg = Message {
+        tags: vec![],
+        prefix: Prefix::Implicit,
+        command: Command::Command(KnownCommand::TOPIC),
+        params: vec![b"#channel", b""],
+    };
+    assert_eq!(msg.to_bytes().unwrap(), &b"TOPIC #channel :\r\n"[..]);
+
+    // Whatever is written parses back to the same message.
+    let messages = vec![
+        msg,
+        Message {
+            tags: vec![(b"a", Some(Cow::Borrowed(b"b; c\\"))), (b"d", None)],
+            prefix: Prefix::User { nick: b"nick", user: Some(b"ident"), host: Some(b"host.com") },
+            command: Command::Command(KnownCommand::PRIVMSG),
+            params: vec![b"#channel", b":-) hi"],
+        },
+        Message {
+            tags: vec![],
+            prefix: Prefix::Server(b"irc.example.com"),
+            command: Command::Numeric(42),
+            params: vec![b"nick", b"x"],
+        },
+    ];
+    for msg in messages {
+        let bytes = msg.to_bytes().unwrap();
+        match message(&bytes) {
+            IResult::Done(b"", parsed) => assert_eq!(parsed, msg),
+            e => panic!("Failed to parse {:?}: {:?}", bytes, e),
+        }
+    }
+
+    // A space in a middle parameter can't be represented.
+    let msg = Message {
+        tags: vec![],
+        prefix: Prefix::Implicit,
+        command: Command::Command(KnownCommand::PRIVMSG),
+        params: vec![b"#a b", b"text"],
+    };
+    assert_eq!(msg.to_bytes().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    // Nor a NUL in a tag value.
+    let msg = Message {
+        tags: vec![(b"a", Some(Cow::Borrowed(b"x\0y")))],
+        prefix: Prefix::Implicit,
+        command: Command::Command(KnownCommand::PING),
+        params: vec![b"x"],
+    };
+    assert_eq!(msg.to_bytes().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    // Neither can invalid commands or prefixes.
+    let invalid = |prefix, command| Message { tags: vec![], prefix, command, params: vec![] }.to_bytes().unwrap_err().kind();
+    let ping = Command::Command(KnownCommand::PING);
+    assert_eq!(invalid(Prefix::Implicit, Command::String(b"")), io::ErrorKind::InvalidInput);
+    assert_eq!(invalid(Prefix::Implicit, Command::String(b"PRIV MSG")), io::ErrorKind::InvalidInput);
+    assert_eq!(invalid(Prefix::Implicit, Command::String(b"CMD1")), io::ErrorKind::InvalidInput);
+    assert_eq!(invalid(Prefix::Implicit, Command::Numeric(1000)), io::ErrorKind::InvalidInput);
+    assert_eq!(invalid(Prefix::Server(b"irc example.com"), ping), io::ErrorKind::InvalidInput);
+    assert_eq!(invalid(Prefix::User { nick: b"ni\0ck", user: None, host: None }, ping), io::ErrorKind::InvalidInput);
+    assert_eq!(invalid(Prefix::User { nick: b"nick", user: Some(b"us\rer"), host: None }, ping), io::ErrorKind::InvalidInput);
+    assert_eq!(invalid(Prefix::User { nick: b"nick", user: None, host: Some(b"host\n") }, ping), io::ErrorKind::InvalidInput);
+    assert_eq!(invalid(Prefix::Server(b"nick!u@h"), ping), io::ErrorKind::InvalidInput);
+    assert_eq!(invalid(Prefix::Server(b""), ping), io::ErrorKind::InvalidInput);
+    assert_eq!(invalid(Prefix::User { nick: b"", user: None, host: None }, ping), io::ErrorKind::InvalidInput);
+    assert_eq!(invalid(Prefix::User { nick: b"ni@ck", user: None, host: Some(b"host") }, ping), io::ErrorKind::InvalidInput);
+    assert_eq!(invalid(Prefix::User { nick: b"nick", user: Some(b"u@h"), host: None }, ping), io::ErrorKind::InvalidInput);
+    let mut buf = vec![];
+    assert!(Prefix::Server(b"irc example.com").write_to(&mut buf).is_err());
+    assert!(Command::Numeric(1000).write_to(&mut buf).is_err());
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn test_owned_message() {
+    let line = b"@badges=global_mod/1,turbo/1;ban-reason=Follow\\sthe\\srules;ccc :nick!ident@host.com PRIVMSG me :Hello world\r\n";
+    let msg = match message(line) {
+        IResult::Done(_, msg) => msg,
+        e => panic!("{:?}", e),
+    };
+    let owned = msg.clone().into_owned();
+    assert_eq!(owned.tags[1], (b"ban-reason".to_vec(), Some(b"Follow the rules".to_vec())));
+    assert_eq!(owned.prefix, OwnedPrefix::User {
+        nick: b"nick".to_vec(),
+        user: Some(b"ident".to_vec()),
+        host: Some(b"host.com".to_vec()),
+    });
+    assert_eq!(owned.borrow(), msg);
+    assert_eq!(owned.to_bytes().unwrap(), &line[..]);
+
+    let owned = OwnedMessage::from(Message {
+        tags: vec![],
+        prefix: Prefix::Server(b"tmi.twitch.tv"),
+        command: Command::String(b"HOSTTARGET"),
+        params: vec![b"#hosting_channel", b"- 99999"],
+    });
+    assert_eq!(owned.command, OwnedCommand::String(b"HOSTTARGET".to_vec()));
+    assert_eq!(Message::from(&owned).command, Command::String(b"HOSTTARGET"));
+}
+
+#[test]
+fn test_parse_message() {
+    let line = b":nick!ident@host.com PRIVMSG me :Hello\r\nPING";
+    match parse_message(line) {
+        Ok(Some((msg, len))) => {
+            assert_eq!(len, line.len() - 4);
+            assert_eq!(msg.params, vec![&b"me"[..], &b"Hello"[..]]);
+        }
+        e => panic!("{:?}", e),
+    }
+    assert_eq!(parse_message(b""), Ok(None));
+    assert_eq!(parse_message(b"PING :tmi.twitch"), Ok(None));
+
+    let error = |kind, offset| Err(ParseError { kind, offset });
+    assert_eq!(parse_message(b"@a=b\0c :nick PRIVMSG me :Hello\r\n"), error(ParseErrorKind::Tags, 4));
+    assert_eq!(parse_message(b":nick!ident@ PRIVMSG me :Hello\r\n"), error(ParseErrorKind::Prefix, 12));
+    assert_eq!(parse_message(b"@a;;b PING x\r\n"), error(ParseErrorKind::Tags, 3));
+    assert_eq!(parse_message(b"@a=1;=b PING x\r\n"), error(ParseErrorKind::Tags, 5));
+    assert_eq!(parse_message(b":nick!\0ident PING x\r\n"), error(ParseErrorKind::Prefix, 6));
+    assert_eq!(parse_message(b":irc.example.com\0 PING x\r\n"), error(ParseErrorKind::Prefix, 16));
+    assert_eq!(parse_message(b"@a=b :nick PRIVMSG me :Hello\r\n").map(|_| ()), Ok(()));
+    assert_eq!(parse_message(b"@a=b :nick.host #PRIVMSG me :Hello\r\n"), error(ParseErrorKind::Command, 16));
+    assert_eq!(parse_message(b"PRIVMSG me\0 :Hello\r\n"), error(ParseErrorKind::Params, 10));
+    assert_eq!(parse_message(b"PRIVMSG me :Hello\n"), error(ParseErrorKind::MissingCrlf, 17));
+    assert_eq!(format!("{}", ParseError { kind: ParseErrorKind::MissingCrlf, offset: 17 }), "message not terminated by CRLF at byte 17");
+}
+
+/// IRCv3 commands.
+#[test]
+fn ircv3_commands() {
+    assert_eq!(message(b":irc.example.com CAP * LS :multi-prefix sasl\r\n"), nom::IResult::Done(&b""[..], Message {
+        tags: vec![],
+        prefix: Prefix::Server(b"irc.example.com"),
+        command: Command::Command(KnownCommand::CAP),
+        params: vec![b"*", b"LS", b"multi-prefix sasl"],
+    }));
+    assert_eq!(message(b"AUTHENTICATE +\r\n"), nom::IResult::Done(&b""[..], Message {
+        tags: vec![],
+        prefix: Prefix::Implicit,
+        command: Command::Command(KnownCommand::AUTHENTICATE),
+        params: vec![b"+"],
+    }));
+    assert_eq!(message(b"@msgid=abc :nick!ident@host.com TAGMSG #channel\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Command(KnownCommand::TAGMSG)));
+    assert_eq!(message(b":irc.example.com BATCH +yXNAbvnRHTRBv netsplit irc.hub other.host\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Command(KnownCommand::BATCH)));
+    assert_eq!(message(b":nick!ident@host.com ACCOUNT accountname\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Command(KnownCommand::ACCOUNT)));
+    assert_eq!(message(b":nick!ident@host.com CHGHOST user new.host.goes.here\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Command(KnownCommand::CHGHOST)));
+    assert_eq!(message(b":nick!ident@host.com SETNAME :Real Name\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Command(KnownCommand::SETNAME)));
+    assert_eq!(message(b"MONITOR + nick\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Command(KnownCommand::MONITOR)));
+    assert_eq!(message(b"FAIL BOX BOXES_INVALID STACK CLOCKWISE :Given boxes are not supported\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Command(KnownCommand::FAIL)));
+    assert_eq!(message(b"WARN REHASH CERTS_EXPIRED :Certificate has expired\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Command(KnownCommand::WARN)));
+    assert_eq!(message(b"NOTE * OPER_MESSAGE :Registering new accounts\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Command(KnownCommand::NOTE)));
+    assert_eq!(message(b"CHATHISTORY LATEST #channel * 50\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Command(KnownCommand::CHATHISTORY)));
+    assert_eq!(message(b"WEBIRC password gateway hostname 192.0.2.1\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Command(KnownCommand::WEBIRC)));
+    assert_eq!(message(b"STARTTLS\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Command(KnownCommand::STARTTLS)));
+}
+
+/// Numerics from the [modern IRC client protocol](https://modern.ircdocs.horse/#numerics).
+#[test]
+fn modern_numerics() {
+    assert_eq!(message(b":irc.example.com 005 nick CASEMAPPING=rfc1459 NETWORK=Example :are supported by this server\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Reply(Reply::ISUPPORT)));
+    assert_eq!(message(b":irc.example.com 330 nick other account :is logged in as\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Reply(Reply::WHOISACCOUNT)));
+    assert_eq!(message(b":irc.example.com 333 nick #channel other 1234567890\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Reply(Reply::TOPICWHOTIME)));
+    assert_eq!(message(b":irc.example.com 671 nick other :is using a secure connection\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Reply(Reply::WHOISSECURE)));
+    assert_eq!(message(b":irc.example.com 670 nick :STARTTLS successful, proceed with TLS handshake\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Reply(Reply::STARTTLS)));
+    assert_eq!(message(b":irc.example.com 691 nick :STARTTLS failed\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Error(Error::STARTTLS)));
+    assert_eq!(message(b":irc.example.com 730 nick :other!ident@host.com\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Reply(Reply::MONONLINE)));
+    assert_eq!(message(b":irc.example.com 734 nick 100 other :Monitor list is full\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Error(Error::MONLISTFULL)));
+    assert_eq!(message(b":irc.example.com 900 nick nick!ident@host.com account :You are now logged in as account\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Reply(Reply::LOGGEDIN)));
+    assert_eq!(message(b":irc.example.com 904 nick :SASL authentication failed\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Error(Error::SASLFAIL)));
+    assert_eq!(message(b":irc.example.com 908 nick PLAIN,EXTERNAL :are available SASL mechanisms\r\n").map(|msg| msg.command), nom::IResult::Done(&b""[..], Command::Reply(Reply::SASLMECHS)));
+
+    match Command::Reply(Reply::BOUNCE) {
+        Command::Reply(Reply::ISUPPORT) => (),
+        _ => panic!("ISUPPORT doesn't match 005"),
+    }
+}
+
+#[test]
+fn test_tag_accessors() {
+    let msg = parse_message(b"@time=2011-10-19T16:40:51.620Z;msgid=63E1033A051D4B41B1AB1FA3CF4B243E;account=hax0r;label=abc;batch=yXNAbvnRHTRBv;bot :nick!ident@host.com PRIVMSG #channel :hello\r\n").unwrap().unwrap().0;
+    assert_eq!(msg.server_time(), Some(time::Timestamp::from_unix_millis(1_319_042_451_620)));
+    assert_eq!(msg.msgid(), Some(&b"63E1033A051D4B41B1AB1FA3CF4B243E"[..]));
+    assert_eq!(msg.account(), Some(&b"hax0r"[..]));
+    assert_eq!(msg.label(), Some(&b"abc"[..]));
+    assert_eq!(msg.batch(), Some(&b"yXNAbvnRHTRBv"[..]));
+    assert!(msg.bot());
+    assert_eq!(msg.tmi_sent_ts(), None);
+
+    let msg = parse_message(b"@time=yesterday;tmi-sent-ts=1319042451620 :nick!ident@host.com PRIVMSG #channel :hello\r\n").unwrap().unwrap().0;
+    assert_eq!(msg.server_time(), None);
+    assert_eq!(msg.tmi_sent_ts().map(|ts| ts.to_rfc3339()), Some("2011-10-19T16:40:51.620Z".to_owned()));
+    assert_eq!(msg.msgid(), None);
+    assert!(!msg.bot());
+}
+
+#[test]
+fn test_tag_key() {
+    assert_eq!(TagKey::parse(b"+typing"), Some(TagKey { client_only: true, vendor: None, name: b"typing" }));
+    assert_eq!(TagKey::parse(b"+draft/reply"), Some(TagKey { client_only: true, vendor: Some(b"draft"), name: b"reply" }));
+    assert_eq!(TagKey::parse(b"example.com/ddd"), Some(TagKey { client_only: false, vendor: Some(b"example.com"), name: b"ddd" }));
+    assert_eq!(TagKey::parse(b"msgid"), Some(TagKey { client_only: false, vendor: None, name: b"msgid" }));
+    for key in &[&b""[..], b"+", b"a_b", b"/a", b"example.com/", b"example..com/a", b"++a", b"a/b/c", b"a+b"] {
+        assert_eq!(TagKey::parse(key), None, "{:?}", key);
+    }
+
+    let msg = parse_message(b"@+typing=active;+example.com/foo=bar :nick!ident@host.com TAGMSG #channel\r\n").unwrap().unwrap().0;
+    assert_eq!(msg.tag(b"+typing"), Some(&b"active"[..]));
+    assert_eq!(TagKey::parse(msg.tags[1].0).and_then(|key| key.vendor), Some(&b"example.com"[..]));
+    assert_eq!(parse_message(b"@=a PING x\r\n").unwrap_err().kind, ParseErrorKind::Tags);
+    assert_eq!(parse_message(b"@a_b=1 PING x\r\n").unwrap_err().kind, ParseErrorKind::Tags);
+    assert_eq!(parse_message(b"@a;;b PING x\r\n").unwrap_err().kind, ParseErrorKind::Tags);
+    assert_eq!(parse_message(b"@a=b; PING x\r\n").unwrap().unwrap().0.tags, vec![(&b"a"[..], Some(Cow::Borrowed(&b"b"[..])))]);
+    assert_eq!(parse_message(b"@a; PING x\r\n").unwrap().unwrap().0.tags, vec![(&b"a"[..], None)]);
+    assert_eq!(parse_message(b"@a;; PING x\r\n").unwrap_err().kind, ParseErrorKind::Tags);
+
+    let mut msg = msg.into_owned();
+    msg.tags.push((b"bad key".to_vec(), None));
+    assert_eq!(msg.to_bytes().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_parser_config() {
+    let error = |kind, offset| Err(ParseError { kind, offset });
+    let strict = ParserConfig::strict();
+    let lenient = ParserConfig::lenient();
+
+    // Strict mode enforces the limits.
+    let fifteen = b"CMD 1 2 3 4 5 6 7 8 9 10 11 12 13 14 :15\r\n";
+    assert_eq!(parse_message_with(fifteen, &strict).map(|msg| msg.unwrap().0.params.len()), Ok(15));
+    assert_eq!(parse_message_with(b"CMD 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16\r\n", &strict), error(ParseErrorKind::Params, 3));
+    assert_eq!(parse_message(b"CMD 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16\r\n").map(|msg| msg.unwrap().0.params.len()), Ok(16));
+    let mut long = b"@a=b PRIVMSG #channel :".to_vec();
+    long.extend_from_slice(&[b'a'; 512]);
+    assert_eq!(parse_message_with(&long, &strict), error(ParseErrorKind::TooLong, 5));
+    assert_eq!(parse_message_with(&long[..400], &strict), Ok(None));
+    let mut tags = b"@a=".to_vec();
+    tags.extend_from_slice(&[b'a'; 8190]);
+    tags.extend_from_slice(b" PING x\r\n");
+    assert_eq!(parse_message_with(&tags, &strict), error(ParseErrorKind::TooLong, 0));
+    assert_eq!(parse_message_with(b"PING  x\r\n", &strict), error(ParseErrorKind::Params, 4));
+    assert_eq!(parse_message_with(b"PING x\0\r\n", &strict), error(ParseErrorKind::Params, 6));
+
+    // Lenient mode accepts deviations.
+    let expected = Message {
+        tags: vec![(b"a", Some(Cow::Borrowed(b"b")))],
+        prefix: Prefix::Server(b"irc.example.com"),
+        command: Command::Command(KnownCommand::PRIVMSG),
+        params: vec![b"#channel", b"hello  world"],
+    };
+    let line = b"@a=b  :irc.example.com   privmsg   #channel   :hello  world\n";
+    assert_eq!(parse_message_with(line, &lenient), Ok(Some((expected.clone(), line.len()))));
+    assert_eq!(message_with(line, &lenient), IResult::Done(&b""[..], expected));
+    assert!(message(line).is_err());
+    assert_eq!(parse_message_with(b"PING x  \r\n", &lenient).unwrap().unwrap().0.params, vec![&b"x"[..]]);
+    assert_eq!(parse_message_with(b"FOO x\n", &lenient).unwrap().unwrap().0.command, Command::String(b"FOO"));
+    assert_eq!(parse_message_with(b"foo x\n", &lenient).unwrap().unwrap().0.command, Command::String(b"foo"));
+    assert_eq!(parse_message_with(b"PING x", &lenient), Ok(None));
+}
+
+#[test]
+fn test_numeric_length() {
+    assert_eq!(parse_message(b"001 nick :Welcome\r\n").unwrap().unwrap().0.command, Command::Reply(Reply::WELCOME));
+    assert_eq!(parse_message(b"999 nick\r\n").unwrap().unwrap().0.command, Command::Numeric(999));
+    assert_eq!(parse_message(b"42 nick\r\n"), Err(ParseError { kind: ParseErrorKind::Command, offset: 0 }));
+    assert_eq!(parse_message(b":irc.example.com 1 nick\r\n"), Err(ParseError { kind: ParseErrorKind::Command, offset: 17 }));
+    assert_eq!(parse_message(b":irc.example.com 0010 nick\r\n"), Err(ParseError { kind: ParseErrorKind::Command, offset: 17 }));
+    assert_eq!(parse_message(b"00"), Ok(None));
+}
+
+/// Feed the parser pseudo-random input and mutations of valid messages. It must never panic.
+#[test]
+fn fuzz_parse_message() {
+    const ALPHABET: &[u8] = b"@:;=!/.+- \\\r\n\0aZ09#\x01\x03\xc3\xa4\xff";
+    const SEEDS: &[&[u8]] = &[
+        b"@badge-info=;badges=broadcaster/1;color=#0D4200 :ronni!ronni@ronni.tmi.twitch.tv PRIVMSG #ronni :Kappa Keepo\r\n",
+        b":irc.example.com 005 nick CHANTYPES=# PREFIX=(ov)@+ :are supported\r\n",
+        b"@+example.com/foo=a\\sb\\ :nick!user@host TAGMSG #channel\r\n",
+        b"001 nick :Welcome\r\n",
+    ];
+
+    // xorshift64
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let configs = [ParserConfig::default(), ParserConfig::strict(), ParserConfig::lenient()];
+    for i in 0..20_000 {
+        let mut input = if i % 2 == 0 {
+            let len = next() % 64;
+            (0..len).map(|_| ALPHABET[next() as usize % ALPHABET.len()]).collect::<Vec<_>>()
+        } else {
+            SEEDS[next() as usize % SEEDS.len()].to_vec()
+        };
+        for _ in 0..next() % 4 {
+            if input.is_empty() {
+                break;
+            }
+            let pos = next() as usize % input.len();
+            match next() % 3 {
+                0 => input[pos] = ALPHABET[next() as usize % ALPHABET.len()],
+                1 => drop(input.remove(pos)),
+                _ => input.insert(pos, ALPHABET[next() as usize % ALPHABET.len()]),
+            }
+        }
+
+        for config in &configs {
+            if let Ok(Some((msg, len))) = parse_message_with(&input, config) {
+                assert!(len <= input.len());
+                let _ = msg.to_bytes();
+                let _ = msg.server_time();
+                let _ = twitch::Command::from_message(&msg);
+            }
+            let _ = message_with(&input, config);
+        }
+    }
+}
+
+#[test]
+fn test_bare_prefix() {
+    let prefix = |line: &[u8]| parse_message(line).unwrap().unwrap().0.prefix.into_owned();
+    let nick = |nick: &[u8]| OwnedPrefix::User { nick: nick.to_vec(), user: None, host: None };
+    assert_eq!(prefix(b":nick QUIT :Bye\r\n"), nick(b"nick"));
+    assert_eq!(prefix(b":old-nick NICK new-nick\r\n"), nick(b"old-nick"));
+    assert_eq!(prefix(b":[away]_ NICK nick\r\n"), nick(b"[away]_"));
+    assert_eq!(prefix(b":irc.example.com NOTICE * :Looking up your hostname\r\n"), OwnedPrefix::Server(b"irc.example.com".to_vec()));
+    assert_eq!(prefix(b":old-nick!user@host NICK new-nick\r\n"), OwnedPrefix::User {
+        nick: b"old-nick".to_vec(),
+        user: Some(b"user".to_vec()),
+        host: Some(b"host".to_vec()),
+    });
+    assert_eq!(parse_message(b": PING x\r\n"), Err(ParseError { kind: ParseErrorKind::Prefix, offset: 1 }));
+    assert_eq!(parse_message(b":!user@host PING x\r\n"), Err(ParseError { kind: ParseErrorKind::Prefix, offset: 1 }));
+}
+
+#[test]
+fn test_cloaked_hosts() {
+    let host = |line: &[u8]| match parse_message(line).unwrap().unwrap().0.prefix {
+        Prefix::User { host, .. } => host.map(<[u8]>::to_vec),
+        prefix => panic!("Not a user prefix: {:?}", prefix),
+    };
+
+    // Libera.Chat
+    assert_eq!(host(b":nick!~user@user/foo/x-123 PRIVMSG #channel :hi\r\n"), Some(b"user/foo/x-123".to_vec()));
+    assert_eq!(host(b":nick!uid12345@gateway/web/irccloud.com/x-abcdefghijklmnop JOIN #channel\r\n"), Some(b"gateway/web/irccloud.com/x-abcdefghijklmnop".to_vec()));
+    assert_eq!(host(b":nick!~nick@unaffiliated/nick QUIT :Quit: Leaving\r\n"), Some(b"unaffiliated/nick".to_vec()));
+    assert_eq!(host(b":nick!~user@2001:db8::1 JOIN #channel\r\n"), Some(b"2001:db8::1".to_vec()));
+    assert_eq!(host(b":nick!~user@::ffff:192.0.2.1 JOIN #channel\r\n"), Some(b"::ffff:192.0.2.1".to_vec()));
+    // OFTC
+    assert_eq!(host(b":nick!~user@00000000.user.oftc.net PRIVMSG #channel :hi\r\n"), Some(b"00000000.user.oftc.net".to_vec()));
+    assert_eq!(host(b":nick!~user@192.0.2.1 PART #channel\r\n"), Some(b"192.0.2.1".to_vec()));
+    // Twitch
+    assert_eq!(host(b":ronni!ronni@ronni.tmi.twitch.tv JOIN #dallas\r\n"), Some(b"ronni.tmi.twitch.tv".to_vec()));
+
+    assert!(parse_message(b":nick!user@host\0name PRIVMSG #channel :hi\r\n").is_err());
+    assert_eq!(parse_message(b":nick!user@host\tname PRIVMSG #channel :hi\r\n"), Err(ParseError { kind: ParseErrorKind::Prefix, offset: 15 }));
+    assert_eq!(parse_message(b":nick!user@\x7fhost PRIVMSG #channel :hi\r\n").unwrap_err().kind, ParseErrorKind::Prefix);
+}
+
+#[test]
+fn test_ip_addr() {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    let ip_addr = |line: &[u8]| parse_message(line).unwrap().unwrap().0.prefix.ip_addr();
+    assert_eq!(ip_addr(b":nick!user@192.0.2.1 JOIN #channel\r\n"), Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+    assert_eq!(ip_addr(b":nick!user@2001:db8::1 JOIN #channel\r\n"), Some(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))));
+    // Some servers prefix IPv6 addresses that start with `:` with a `0`.
+    assert_eq!(ip_addr(b":nick!user@0::1 JOIN #channel\r\n"), Some(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+    for line in &[
+        &b":nick!user@1...1 JOIN #channel\r\n"[..],
+        b":nick!user@::::: JOIN #channel\r\n",
+        b":nick!user@256.0.0.1 JOIN #channel\r\n",
+        b":nick!user@1.2.3 JOIN #channel\r\n",
+        b":nick!user@2001:db8::1::2 JOIN #channel\r\n",
+        b":nick!user@user/foo/x-123 JOIN #channel\r\n",
+        b":nick!user@deadbeef.example.com JOIN #channel\r\n",
+        b":nick!user JOIN #channel\r\n",
+        b":192.0.2.1 NOTICE * :Server named by its address\r\n",
+    ] {
+        assert_eq!(ip_addr(line), None, "{:?}", line);
+    }
+}
@@ -0,0 +1,222 @@
+//! Hostmasks such as `nick!*@*.example.com` used in ban and ignore lists.
+
+use casemapping::CaseMapping;
+use Prefix;
+
+/// Part of a parsed mask.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    /// Bytes that must match literally (up to case).
+    Literal(Vec<u8>),
+    /// `?`: any single byte.
+    One,
+    /// `*`: any number of bytes.
+    Many,
+}
+
+/// Byte-level token used while matching.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Atom {
+    Byte(u8),
+    One,
+    Many,
+}
+
+/// Glob pattern matched against `nick!user@host`.
+///
+/// `*` matches any number of characters and `?` matches exactly one. A backslash makes the
+/// following character match literally, so `\*`, `\?` and `\\` match `*`, `?` and `\`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mask {
+    mask: Vec<u8>,
+    tokens: Vec<Token>,
+}
+
+fn tokenize(mask: &[u8]) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut literal = vec![];
+    let mut bytes = mask.iter();
+    while let Some(&b) = bytes.next() {
+        let token = match b {
+            b'*' => Token::Many,
+            b'?' => Token::One,
+            b'\\' => {
+                literal.push(bytes.next().cloned().unwrap_or(b'\\'));
+                continue;
+            }
+            b => {
+                literal.push(b);
+                continue;
+            }
+        };
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(literal));
+            literal = vec![];
+        }
+        tokens.push(token);
+    }
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+    tokens
+}
+
+/// Whether `mask` contains `needle` outside of an escape sequence.
+fn contains_unescaped(mask: &[u8], needle: u8) -> bool {
+    let mut bytes = mask.iter();
+    while let Some(&b) = bytes.next() {
+        if b == b'\\' {
+            bytes.next();
+        } else if b == needle {
+            return true;
+        }
+    }
+    false
+}
+
+/// Escape `*`, `?` and `\` so that `value` is matched literally.
+pub fn escape(value: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(value.len());
+    for &b in value {
+        if b == b'*' || b == b'?' || b == b'\\' {
+            escaped.push(b'\\');
+        }
+        escaped.push(b);
+    }
+    escaped
+}
+
+impl Mask {
+    /// Parse a mask. Partial masks are completed: `nick` becomes `nick!*@*`, `nick!user` becomes
+    /// `nick!user@*` and `user@host` becomes `*!user@host`. Escaped `\!` and `\@` don't count as
+    /// separators.
+    pub fn new(mask: &[u8]) -> Mask {
+        let mut full = vec![];
+        match (contains_unescaped(mask, b'!'), contains_unescaped(mask, b'@')) {
+            (true, true) => full.extend_from_slice(mask),
+            (true, false) => {
+                full.extend_from_slice(mask);
+                full.extend_from_slice(b"@*");
+            }
+            (false, true) => {
+                full.extend_from_slice(b"*!");
+                full.extend_from_slice(mask);
+            }
+            (false, false) => {
+                full.extend_from_slice(mask);
+                full.extend_from_slice(b"!*@*");
+            }
+        }
+
+        Mask {
+            tokens: tokenize(&full),
+            mask: full,
+        }
+    }
+
+    /// Completed mask.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.mask
+    }
+
+    /// Match the mask against a `nick!user@host` string.
+    pub fn matches_bytes(&self, casemapping: CaseMapping, subject: &[u8]) -> bool {
+        let mut atoms = vec![];
+        for token in &self.tokens {
+            match *token {
+                Token::Literal(ref literal) => atoms.extend(casemapping.fold(literal).iter().map(|&b| Atom::Byte(b))),
+                Token::One => atoms.push(Atom::One),
+                Token::Many => atoms.push(Atom::Many),
+            }
+        }
+        glob(&atoms, &casemapping.fold(subject))
+    }
+
+    /// Match the mask against a message source. A missing username or hostname is treated as
+    /// empty and a server name as a nickname. `Prefix::Implicit` never matches.
+    pub fn matches(&self, casemapping: CaseMapping, prefix: &Prefix) -> bool {
+        let (nick, user, host) = match *prefix {
+            Prefix::User { nick, user, host } => (nick, user.unwrap_or(b""), host.unwrap_or(b"")),
+            Prefix::Server(name) => (name, &b""[..], &b""[..]),
+            Prefix::Implicit => return false,
+        };
+        let mut subject = Vec::with_capacity(nick.len() + user.len() + host.len() + 2);
+        subject.extend_from_slice(nick);
+        subject.push(b'!');
+        subject.extend_from_slice(user);
+        subject.push(b'@');
+        subject.extend_from_slice(host);
+        self.matches_bytes(casemapping, &subject)
+    }
+}
+
+/// Wildcard matching with backtracking to the last `*`.
+fn glob(pattern: &[Atom], subject: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    let mut backtrack = None;
+    while s < subject.len() {
+        match pattern.get(p) {
+            Some(&Atom::Many) => {
+                backtrack = Some((p, s));
+                p += 1;
+                continue;
+            }
+            Some(&Atom::One) => {
+                p += 1;
+                s += 1;
+                continue;
+            }
+            Some(&Atom::Byte(b)) if b == subject[s] => {
+                p += 1;
+                s += 1;
+                continue;
+            }
+            _ => (),
+        }
+        match backtrack {
+            Some((star, matched)) => {
+                p = star + 1;
+                s = matched + 1;
+                backtrack = Some((star, matched + 1));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&atom| atom == Atom::Many)
+}
+
+#[test]
+fn test_new() {
+    assert_eq!(Mask::new(b"nick").as_bytes(), b"nick!*@*");
+    assert_eq!(Mask::new(b"nick!user").as_bytes(), b"nick!user@*");
+    assert_eq!(Mask::new(b"*@host.com").as_bytes(), b"*!*@host.com");
+    assert_eq!(Mask::new(b"nick!user@host.com").as_bytes(), b"nick!user@host.com");
+    assert_eq!(Mask::new(b"a\\!b").as_bytes(), b"a\\!b!*@*");
+    assert_eq!(Mask::new(b"a\\\\!b").as_bytes(), b"a\\\\!b@*");
+    assert_eq!(Mask::new(b"a!b\\@c").as_bytes(), b"a!b\\@c@*");
+}
+
+#[test]
+fn test_matches() {
+    let prefix = Prefix::User {
+        nick: b"Foo[away]",
+        user: Some(b"~foo"),
+        host: Some(b"user/foo"),
+    };
+    assert!(Mask::new(b"foo{away}").matches(CaseMapping::Rfc1459, &prefix));
+    assert!(!Mask::new(b"foo{away}").matches(CaseMapping::Ascii, &prefix));
+    assert!(Mask::new(b"*!*@user/*").matches(CaseMapping::Rfc1459, &prefix));
+    assert!(Mask::new(b"F??[*]!*foo@*").matches(CaseMapping::Ascii, &prefix));
+    assert!(!Mask::new(b"F?[*]!*foo@*").matches(CaseMapping::Ascii, &prefix));
+    assert!(!Mask::new(b"*!*@*.example.com").matches(CaseMapping::Rfc1459, &prefix));
+    assert!(!Mask::new(b"*").matches(CaseMapping::Rfc1459, &Prefix::Implicit));
+
+    // Escaped wildcards match literally.
+    assert!(Mask::new(b"a\\*b").matches_bytes(CaseMapping::Ascii, b"a*b!u@h"));
+    assert!(!Mask::new(b"a\\*b").matches_bytes(CaseMapping::Ascii, b"axb!u@h"));
+    assert!(Mask::new(b"a\\?\\\\").matches_bytes(CaseMapping::Ascii, b"a?\\!u@h"));
+    // The escape character isn't affected by casemapping.
+    assert!(Mask::new(b"a\\\\").matches_bytes(CaseMapping::Rfc1459, b"a|!u@h"));
+    assert_eq!(escape(b"a*b?c\\"), b"a\\*b\\?c\\\\".to_vec());
+    assert!(Mask::new(&escape(b"*")).matches_bytes(CaseMapping::Ascii, b"*!u@h"));
+}
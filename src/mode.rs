@@ -0,0 +1,230 @@
+//! Parsing and building of [mode strings](https://modern.ircdocs.horse/#mode-message) such as
+//! `+ovk-b nick1 nick2 key mask`.
+
+use isupport::{ChanModes, ISupport};
+use {Command, KnownCommand, Message, OwnedCommand, OwnedMessage, OwnedPrefix, Reply};
+
+/// How a mode treats its argument.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModeType {
+    /// `CHANMODES` type A: adds or removes an address to or from a list. Without an argument
+    /// the list is queried.
+    List,
+    /// `CHANMODES` type B: a setting that always takes an argument.
+    Parameter,
+    /// `CHANMODES` type C: a setting that only takes an argument when set.
+    SetParameter,
+    /// `CHANMODES` type D or a user mode: a setting that never takes an argument.
+    Flag,
+    /// Channel membership mode from `PREFIX`, e.g. `o` or `v`. Always takes a nickname.
+    Membership,
+    /// Mode that the server didn't advertise. Assumed to not take an argument.
+    Unknown,
+}
+
+/// Single mode change.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ModeChange<'a> {
+    /// Whether the mode is set (`+`) or unset (`-`).
+    pub set: bool,
+    /// Mode letter.
+    pub mode: u8,
+    /// How the mode treats its argument.
+    pub mode_type: ModeType,
+    /// Argument of the mode.
+    pub arg: Option<&'a [u8]>,
+}
+
+/// Classify a channel mode according to `CHANMODES` and `PREFIX`.
+pub fn channel_mode_type(isupport: &ISupport, mode: u8) -> ModeType {
+    classify(&isupport.chanmodes(), &isupport.prefix(), mode)
+}
+
+fn classify(chanmodes: &ChanModes, prefix: &[(u8, u8)], mode: u8) -> ModeType {
+    if prefix.iter().any(|&(m, _)| m == mode) {
+        ModeType::Membership
+    } else if chanmodes.a.contains(&mode) {
+        ModeType::List
+    } else if chanmodes.b.contains(&mode) {
+        ModeType::Parameter
+    } else if chanmodes.c.contains(&mode) {
+        ModeType::SetParameter
+    } else if chanmodes.d.contains(&mode) {
+        ModeType::Flag
+    } else {
+        ModeType::Unknown
+    }
+}
+
+fn parse<'a, F>(modes: &'a [u8], args: &[&'a [u8]], mode_type: F) -> Option<Vec<ModeChange<'a>>>
+where
+    F: Fn(u8) -> ModeType,
+{
+    let mut args = args.iter();
+    let mut set = true;
+    let mut changes = vec![];
+
+    for &mode in modes {
+        match mode {
+            b'+' => set = true,
+            b'-' => set = false,
+            mode => {
+                let mode_type = mode_type(mode);
+                let arg = match mode_type {
+                    ModeType::List => args.next().cloned(),
+                    ModeType::Parameter | ModeType::Membership => Some(*args.next()?),
+                    ModeType::SetParameter if set => Some(*args.next()?),
+                    ModeType::SetParameter | ModeType::Flag | ModeType::Unknown => None,
+                };
+                changes.push(ModeChange { set, mode, mode_type, arg });
+            }
+        }
+    }
+
+    Some(changes)
+}
+
+/// Parse a channel mode string and its arguments. Returns `None` if a mode is missing its
+/// argument.
+pub fn parse_channel_modes<'a>(isupport: &ISupport, modes: &'a [u8], args: &[&'a [u8]]) -> Option<Vec<ModeChange<'a>>> {
+    let chanmodes = isupport.chanmodes();
+    let prefix = isupport.prefix();
+    parse(modes, args, |mode| classify(&chanmodes, &prefix, mode))
+}
+
+/// Parse a user mode string. User modes never take an argument.
+pub fn parse_user_modes(modes: &[u8]) -> Vec<ModeChange<'_>> {
+    parse(modes, &[], |_| ModeType::Flag).unwrap_or_else(Vec::new)
+}
+
+/// Parse the mode changes in a `MODE` message or an `RPL_CHANNELMODEIS` or `RPL_UMODEIS` reply.
+/// Targets that start with one of the `CHANTYPES` are treated as channels, anything else as
+/// users. Returns `None` for other messages or if a mode is missing its argument.
+pub fn from_message<'a>(isupport: &ISupport, message: &Message<'a>) -> Option<Vec<ModeChange<'a>>> {
+    let params = &message.params[..];
+    let (target, rest) = match message.command {
+        Command::Command(KnownCommand::MODE) => (params.first()?, &params[1..]),
+        Command::Reply(Reply::CHANNELMODEIS) => (params.get(1)?, &params[2..]),
+        Command::Reply(Reply::UMODEIS) => return Some(parse_user_modes(params.get(1)?)),
+        _ => return None,
+    };
+    let (modes, args) = match rest.split_first() {
+        Some((modes, args)) => (*modes, args),
+        None => return Some(vec![]),
+    };
+
+    if target.first().is_some_and(|b| isupport.chantypes().contains(b)) {
+        parse_channel_modes(isupport, modes, args)
+    } else {
+        Some(parse_user_modes(modes))
+    }
+}
+
+/// Build the mode string and arguments for a list of mode changes. Returns no parameters if
+/// there are no changes.
+pub fn to_params(changes: &[ModeChange]) -> Vec<Vec<u8>> {
+    if changes.is_empty() {
+        return vec![];
+    }
+
+    let mut modes = vec![];
+    let mut args = vec![];
+    let mut set = None;
+    for change in changes {
+        if set != Some(change.set) {
+            modes.push(if change.set { b'+' } else { b'-' });
+            set = Some(change.set);
+        }
+        modes.push(change.mode);
+        if let Some(arg) = change.arg {
+            args.push(arg.to_vec());
+        }
+    }
+
+    let mut params = vec![modes];
+    params.extend(args);
+    params
+}
+
+/// Build a `MODE` message that applies `changes` to `target`. Returns `None` if there are no
+/// changes, as `MODE target` alone would query the modes instead.
+pub fn to_message(target: &[u8], changes: &[ModeChange]) -> Option<OwnedMessage> {
+    if changes.is_empty() {
+        return None;
+    }
+
+    let mut params = vec![target.to_vec()];
+    params.extend(to_params(changes));
+    Some(OwnedMessage {
+        tags: vec![],
+        prefix: OwnedPrefix::Implicit,
+        command: OwnedCommand::Command(KnownCommand::MODE),
+        params,
+    })
+}
+
+#[test]
+fn test_parse_channel_modes() {
+    let mut isupport = ISupport::new();
+    isupport.add_token(b"CHANMODES=beI,k,l,imnpst");
+    isupport.add_token(b"PREFIX=(ohv)@%+");
+
+    let change = |set, mode, mode_type, arg| ModeChange { set, mode, mode_type, arg };
+    assert_eq!(parse_channel_modes(&isupport, b"+ovk-b", &[b"nick1", b"nick2", b"key", b"mask"]), Some(vec![
+        change(true, b'o', ModeType::Membership, Some(&b"nick1"[..])),
+        change(true, b'v', ModeType::Membership, Some(&b"nick2"[..])),
+        change(true, b'k', ModeType::Parameter, Some(&b"key"[..])),
+        change(false, b'b', ModeType::List, Some(&b"mask"[..])),
+    ]));
+    assert_eq!(parse_channel_modes(&isupport, b"+l-lnX", &[b"10"]), Some(vec![
+        change(true, b'l', ModeType::SetParameter, Some(&b"10"[..])),
+        change(false, b'l', ModeType::SetParameter, None),
+        change(false, b'n', ModeType::Flag, None),
+        change(false, b'X', ModeType::Unknown, None),
+    ]));
+    assert_eq!(parse_channel_modes(&isupport, b"+b", &[]), Some(vec![change(true, b'b', ModeType::List, None)]));
+    assert_eq!(parse_channel_modes(&isupport, b"+o", &[]), None);
+    assert_eq!(parse_user_modes(b"+iw-x"), vec![
+        change(true, b'i', ModeType::Flag, None),
+        change(true, b'w', ModeType::Flag, None),
+        change(false, b'x', ModeType::Flag, None),
+    ]);
+}
+
+#[test]
+fn test_from_message() {
+    let isupport = ISupport::new();
+
+    let msg = ::parse_message(b":jtv MODE #channel +o operator_user\r\n").unwrap().unwrap().0;
+    assert_eq!(from_message(&isupport, &msg), Some(vec![
+        ModeChange { set: true, mode: b'o', mode_type: ModeType::Membership, arg: Some(b"operator_user") },
+    ]));
+    let msg = ::parse_message(b":irc.example.com 324 nick #channel +kl key 10\r\n").unwrap().unwrap().0;
+    assert_eq!(from_message(&isupport, &msg), Some(vec![
+        ModeChange { set: true, mode: b'k', mode_type: ModeType::Parameter, arg: Some(b"key") },
+        ModeChange { set: true, mode: b'l', mode_type: ModeType::SetParameter, arg: Some(b"10") },
+    ]));
+    let msg = ::parse_message(b":nick MODE nick :+i\r\n").unwrap().unwrap().0;
+    assert_eq!(from_message(&isupport, &msg), Some(vec![
+        ModeChange { set: true, mode: b'i', mode_type: ModeType::Flag, arg: None },
+    ]));
+    let msg = ::parse_message(b":irc.example.com 221 nick +iw\r\n").unwrap().unwrap().0;
+    assert_eq!(from_message(&isupport, &msg).map(|changes| changes.len()), Some(2));
+}
+
+#[test]
+fn test_build() {
+    let changes = [
+        ModeChange { set: true, mode: b'o', mode_type: ModeType::Membership, arg: Some(b"nick1") },
+        ModeChange { set: true, mode: b'v', mode_type: ModeType::Membership, arg: Some(b"nick2") },
+        ModeChange { set: false, mode: b'b', mode_type: ModeType::List, arg: Some(b"*!*@host") },
+        ModeChange { set: false, mode: b'n', mode_type: ModeType::Flag, arg: None },
+    ];
+    assert_eq!(to_message(b"#channel", &changes).unwrap().to_bytes().unwrap(), &b"MODE #channel +ov-bn nick1 nick2 *!*@host\r\n"[..]);
+    assert_eq!(to_params(&[]), Vec::<Vec<u8>>::new());
+    assert_eq!(to_message(b"#channel", &[]), None);
+
+    let isupport = ISupport::new();
+    let msg = ::parse_message(b"MODE #channel +ov-bn nick1 nick2 *!*@host\r\n").unwrap().unwrap().0;
+    assert_eq!(from_message(&isupport, &msg), Some(changes.to_vec()));
+}
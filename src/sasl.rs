@@ -0,0 +1,553 @@
+//! Sans-IO [SASL authentication](https://ircv3.net/specs/extensions/sasl-3.1).
+//!
+//! `Authenticator` consumes `AUTHENTICATE` messages and the SASL numerics and produces the
+//! `AUTHENTICATE` messages that should be sent in response. Combine it with
+//! `cap::Negotiator::hold_end` to authenticate before registration completes.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use {Command, Error, KnownCommand, Message, OwnedCommand, OwnedMessage, OwnedPrefix, Reply};
+
+/// Maximum length of the base64 payload in a single `AUTHENTICATE` message.
+pub const CHUNK_LEN: usize = 400;
+/// Maximum length of the base64 challenge collected from `AUTHENTICATE` chunks. Longer
+/// challenges abort the exchange.
+pub const MAX_CHALLENGE_LEN: usize = 8192;
+/// Largest SCRAM iteration count that is accepted. A server asking for more is treated as an
+/// invalid challenge rather than burning CPU time on the client's behalf.
+pub const MAX_SCRAM_ITERATIONS: u32 = 100_000;
+
+/// Supported mechanisms.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mechanism {
+    /// Username and password in plain text, [RFC 4616](https://tools.ietf.org/html/rfc4616).
+    Plain,
+    /// Credentials established outside of SASL, usually a TLS client certificate.
+    External,
+    /// Salted challenge-response, [RFC 7677](https://tools.ietf.org/html/rfc7677).
+    ScramSha256,
+}
+
+impl Mechanism {
+    /// Look up a mechanism by its name.
+    pub fn from_name(name: &[u8]) -> Option<Mechanism> {
+        match name {
+            b"PLAIN" => Some(Mechanism::Plain),
+            b"EXTERNAL" => Some(Mechanism::External),
+            b"SCRAM-SHA-256" => Some(Mechanism::ScramSha256),
+            _ => None,
+        }
+    }
+
+    /// Name of the mechanism as used in `AUTHENTICATE` and `RPL_SASLMECHS`.
+    pub fn name(&self) -> &'static [u8] {
+        match *self {
+            Mechanism::Plain => b"PLAIN",
+            Mechanism::External => b"EXTERNAL",
+            Mechanism::ScramSha256 => b"SCRAM-SHA-256",
+        }
+    }
+}
+
+/// Reason for a failed authentication.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// `ERR_NICKLOCKED`: the account is unavailable.
+    NickLocked,
+    /// `ERR_SASLFAIL`: invalid credentials or an unsupported mechanism.
+    Rejected,
+    /// `ERR_SASLTOOLONG`: the payload was too long.
+    TooLong,
+    /// `ERR_SASLABORTED`: authentication was aborted.
+    Aborted,
+    /// `ERR_SASLALREADY`: the client is already authenticated.
+    AlreadyAuthenticated,
+    /// The server sent a malformed challenge or failed to prove that it knows the password.
+    /// Authentication was aborted by the client.
+    InvalidChallenge,
+}
+
+/// Authentication progress.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum State {
+    /// `start` hasn't been called yet.
+    Idle,
+    /// Waiting for the server.
+    Authenticating,
+    /// `RPL_SASLSUCCESS` was received.
+    Succeeded,
+    /// Authentication failed.
+    Failed(Failure),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum ScramStep {
+    ClientFirst,
+    ClientFinal { server_signature: [u8; 32] },
+    Verified,
+}
+
+#[derive(Clone, Debug)]
+struct Scram {
+    password: Vec<u8>,
+    client_first_bare: Vec<u8>,
+    nonce: Vec<u8>,
+    step: ScramStep,
+}
+
+#[derive(Clone, Debug)]
+enum Client {
+    /// Mechanism that answers the first challenge and is done. `None` once the answer is sent.
+    Single(Option<Vec<u8>>),
+    Scram(Scram),
+}
+
+/// SASL authentication state machine.
+#[derive(Clone, Debug)]
+pub struct Authenticator {
+    mechanism: Mechanism,
+    client: Client,
+    state: State,
+    buffer: Vec<u8>,
+    account: Option<Vec<u8>>,
+    mechanisms: Vec<Vec<u8>>,
+}
+
+fn authenticate(param: Vec<u8>) -> OwnedMessage {
+    OwnedMessage {
+        tags: vec![],
+        prefix: OwnedPrefix::Implicit,
+        command: OwnedCommand::Command(KnownCommand::AUTHENTICATE),
+        params: vec![param],
+    }
+}
+
+/// Encode a payload as `AUTHENTICATE` messages. The base64 payload is split into
+/// `CHUNK_LEN`-byte chunks and followed by `AUTHENTICATE +` if the last chunk is full or the
+/// payload is empty.
+pub fn encode(payload: &[u8]) -> Vec<OwnedMessage> {
+    let encoded = STANDARD.encode(payload).into_bytes();
+    let mut messages = encoded.chunks(CHUNK_LEN).map(|chunk| authenticate(chunk.to_vec())).collect::<Vec<_>>();
+    if encoded.len() % CHUNK_LEN == 0 {
+        messages.push(authenticate(b"+".to_vec()));
+    }
+    messages
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    let mut out = [0; 32];
+    out.copy_from_slice(&mac.finalize().into_bytes());
+    out
+}
+
+/// `Hi` from RFC 5802, i.e. PBKDF2 with HMAC-SHA-256 and a single block.
+fn hi(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut block = salt.to_vec();
+    block.extend_from_slice(&[0, 0, 0, 1]);
+    let mut u = hmac(password, &block);
+    let mut result = u;
+    for _ in 1..iterations {
+        u = hmac(password, &u);
+        for (r, b) in result.iter_mut().zip(&u) {
+            *r ^= b;
+        }
+    }
+    result
+}
+
+/// Escape `=` and `,` in a SCRAM username.
+fn scram_name(name: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(name.len());
+    for &b in name {
+        match b {
+            b'=' => escaped.extend_from_slice(b"=3D"),
+            b',' => escaped.extend_from_slice(b"=2C"),
+            b => escaped.push(b),
+        }
+    }
+    escaped
+}
+
+/// Look up an attribute in a SCRAM message such as `r=nonce,s=salt,i=4096`.
+fn scram_attribute(message: &[u8], name: u8) -> Option<&[u8]> {
+    message.split(|&b| b == b',')
+        .find(|attr| attr.len() >= 2 && attr[0] == name && attr[1] == b'=')
+        .map(|attr| &attr[2..])
+}
+
+impl Scram {
+    fn new(username: &[u8], password: &[u8], nonce: &[u8]) -> Scram {
+        let mut client_first_bare = b"n=".to_vec();
+        client_first_bare.extend(scram_name(username));
+        client_first_bare.extend_from_slice(b",r=");
+        client_first_bare.extend_from_slice(nonce);
+        Scram {
+            password: password.to_vec(),
+            client_first_bare,
+            nonce: nonce.to_vec(),
+            step: ScramStep::ClientFirst,
+        }
+    }
+
+    fn respond(&mut self, challenge: &[u8]) -> Option<Vec<u8>> {
+        match self.step {
+            ScramStep::ClientFirst if challenge.is_empty() => {
+                let mut client_first = b"n,,".to_vec();
+                client_first.extend_from_slice(&self.client_first_bare);
+                Some(client_first)
+            }
+            ScramStep::ClientFirst => {
+                // Mandatory extensions aren't supported.
+                if challenge.starts_with(b"m=") {
+                    return None;
+                }
+                let nonce = scram_attribute(challenge, b'r')?;
+                // The server must append its own part to the client nonce.
+                if nonce.len() <= self.nonce.len() || !nonce.starts_with(&self.nonce) {
+                    return None;
+                }
+                let salt = STANDARD.decode(scram_attribute(challenge, b's')?).ok()?;
+                let iterations = ::std::str::from_utf8(scram_attribute(challenge, b'i')?).ok()?.parse().ok()?;
+                if iterations == 0 || iterations > MAX_SCRAM_ITERATIONS {
+                    return None;
+                }
+
+                let salted_password = hi(&self.password, &salt, iterations);
+                let client_key = hmac(&salted_password, b"Client Key");
+                let stored_key = Sha256::digest(client_key);
+                let server_key = hmac(&salted_password, b"Server Key");
+
+                // `biws` is the base64 encoded GS2 header `n,,`.
+                let mut client_final = b"c=biws,r=".to_vec();
+                client_final.extend_from_slice(nonce);
+                let mut auth_message = self.client_first_bare.clone();
+                auth_message.push(b',');
+                auth_message.extend_from_slice(challenge);
+                auth_message.push(b',');
+                auth_message.extend_from_slice(&client_final);
+
+                let mut proof = hmac(&stored_key, &auth_message);
+                for (p, k) in proof.iter_mut().zip(&client_key) {
+                    *p ^= k;
+                }
+                client_final.extend_from_slice(b",p=");
+                client_final.extend(STANDARD.encode(proof).into_bytes());
+
+                self.step = ScramStep::ClientFinal {
+                    server_signature: hmac(&server_key, &auth_message),
+                };
+                Some(client_final)
+            }
+            ScramStep::ClientFinal { server_signature } => {
+                let verifier = STANDARD.decode(scram_attribute(challenge, b'v')?).ok()?;
+                if verifier[..] != server_signature[..] {
+                    return None;
+                }
+                self.step = ScramStep::Verified;
+                Some(vec![])
+            }
+            ScramStep::Verified => None,
+        }
+    }
+}
+
+impl Client {
+    fn respond(&mut self, challenge: &[u8]) -> Option<Vec<u8>> {
+        match *self {
+            Client::Single(ref mut payload) => payload.take(),
+            Client::Scram(ref mut scram) => scram.respond(challenge),
+        }
+    }
+
+    /// Whether the server has been verified, if the mechanism supports that.
+    fn is_complete(&self) -> bool {
+        match *self {
+            Client::Single(_) => true,
+            Client::Scram(ref scram) => scram.step == ScramStep::Verified,
+        }
+    }
+}
+
+impl Authenticator {
+    fn new(mechanism: Mechanism, client: Client) -> Authenticator {
+        Authenticator {
+            mechanism,
+            client,
+            state: State::Idle,
+            buffer: vec![],
+            account: None,
+            mechanisms: vec![],
+        }
+    }
+
+    /// Authenticate with `PLAIN`. `username` is used as both the authentication and the
+    /// authorization identity.
+    pub fn plain(username: &[u8], password: &[u8]) -> Authenticator {
+        let mut payload = username.to_vec();
+        payload.push(0);
+        payload.extend_from_slice(username);
+        payload.push(0);
+        payload.extend_from_slice(password);
+        Authenticator::new(Mechanism::Plain, Client::Single(Some(payload)))
+    }
+
+    /// Authenticate with `EXTERNAL`, e.g. using a TLS client certificate.
+    pub fn external() -> Authenticator {
+        Authenticator::new(Mechanism::External, Client::Single(Some(vec![])))
+    }
+
+    /// Authenticate with `SCRAM-SHA-256`. `nonce` must be a random printable ASCII string
+    /// without commas. Usernames and passwords are not normalized with SASLprep.
+    pub fn scram_sha256(username: &[u8], password: &[u8], nonce: &[u8]) -> Authenticator {
+        Authenticator::new(Mechanism::ScramSha256, Client::Scram(Scram::new(username, password, nonce)))
+    }
+
+    /// Mechanism in use.
+    pub fn mechanism(&self) -> Mechanism {
+        self.mechanism
+    }
+
+    /// Current state.
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Whether authentication has finished, successfully or not.
+    pub fn is_done(&self) -> bool {
+        match self.state {
+            State::Succeeded | State::Failed(_) => true,
+            State::Idle | State::Authenticating => false,
+        }
+    }
+
+    /// Account name from `RPL_LOGGEDIN`.
+    pub fn account(&self) -> Option<&[u8]> {
+        self.account.as_ref().map(|account| &account[..])
+    }
+
+    /// Mechanisms the server supports according to `RPL_SASLMECHS`.
+    pub fn mechanisms(&self) -> &[Vec<u8>] {
+        &self.mechanisms
+    }
+
+    /// Start authentication. Returns `AUTHENTICATE <mechanism>`.
+    pub fn start(&mut self) -> OwnedMessage {
+        self.state = State::Authenticating;
+        authenticate(self.mechanism.name().to_vec())
+    }
+
+    /// Abort authentication. Returns `AUTHENTICATE *`.
+    pub fn abort(&mut self) -> OwnedMessage {
+        if self.state == State::Authenticating {
+            self.state = State::Failed(Failure::Aborted);
+        }
+        authenticate(b"*".to_vec())
+    }
+
+    fn fail(&mut self, failure: Failure) {
+        if self.state == State::Authenticating {
+            self.state = State::Failed(failure);
+        }
+    }
+
+    /// Process a message from the server. Returns the messages that should be sent in
+    /// response. Messages other than `AUTHENTICATE` and the SASL numerics are ignored.
+    pub fn handle(&mut self, message: &Message) -> Vec<OwnedMessage> {
+        match message.command {
+            Command::Command(KnownCommand::AUTHENTICATE) if self.state == State::Authenticating => {
+                let chunk = match message.params.first() {
+                    Some(chunk) => *chunk,
+                    None => return vec![],
+                };
+                if chunk != b"+" {
+                    self.buffer.extend_from_slice(chunk);
+                }
+                let too_long = self.buffer.len() > MAX_CHALLENGE_LEN;
+                if chunk.len() == CHUNK_LEN && !too_long {
+                    return vec![];
+                }
+
+                let challenge = STANDARD.decode(&self.buffer);
+                self.buffer.clear();
+                match challenge.ok().filter(|_| !too_long).and_then(|challenge| self.client.respond(&challenge)) {
+                    Some(response) => encode(&response),
+                    None => {
+                        let abort = self.abort();
+                        self.state = State::Failed(Failure::InvalidChallenge);
+                        vec![abort]
+                    }
+                }
+            }
+            Command::Reply(Reply::LOGGEDIN) => {
+                self.account = message.params.get(2).map(|account| account.to_vec());
+                vec![]
+            }
+            Command::Reply(Reply::LOGGEDOUT) => {
+                self.account = None;
+                vec![]
+            }
+            Command::Reply(Reply::SASLSUCCESS) if self.state == State::Authenticating => {
+                if self.client.is_complete() {
+                    self.state = State::Succeeded;
+                } else {
+                    self.state = State::Failed(Failure::InvalidChallenge);
+                }
+                vec![]
+            }
+            Command::Reply(Reply::SASLMECHS) => {
+                if let Some(mechanisms) = message.params.get(1) {
+                    self.mechanisms = mechanisms.split(|&b| b == b',').map(<[u8]>::to_vec).collect();
+                }
+                vec![]
+            }
+            Command::Error(Error::NICKLOCKED) => {
+                self.fail(Failure::NickLocked);
+                vec![]
+            }
+            Command::Error(Error::SASLFAIL) => {
+                self.fail(Failure::Rejected);
+                vec![]
+            }
+            Command::Error(Error::SASLTOOLONG) => {
+                self.fail(Failure::TooLong);
+                vec![]
+            }
+            Command::Error(Error::SASLABORTED) => {
+                self.fail(Failure::Aborted);
+                vec![]
+            }
+            Command::Error(Error::SASLALREADY) => {
+                self.fail(Failure::AlreadyAuthenticated);
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+fn exchange(authenticator: &mut Authenticator, line: &[u8]) -> Vec<Vec<u8>> {
+    let msg = ::parse_message(line).unwrap().unwrap().0;
+    authenticator.handle(&msg).iter().map(|msg| msg.to_bytes().unwrap()).collect()
+}
+
+#[test]
+fn test_encode() {
+    let lines = |payload: &[u8]| encode(payload).iter().map(|msg| msg.to_bytes().unwrap()).collect::<Vec<_>>();
+    assert_eq!(lines(b""), vec![b"AUTHENTICATE +\r\n".to_vec()]);
+    assert_eq!(lines(b"jilles\0jilles\0sesame"), vec![b"AUTHENTICATE amlsbGVzAGppbGxlcwBzZXNhbWU=\r\n".to_vec()]);
+
+    // 300 bytes encode to exactly 400 bytes of base64.
+    let full = lines(&[b'a'; 300]);
+    assert_eq!(full.len(), 2);
+    assert_eq!(full[0].len(), "AUTHENTICATE ".len() + CHUNK_LEN + 2);
+    assert_eq!(full[1], b"AUTHENTICATE +\r\n".to_vec());
+    let longer = lines(&[b'a'; 301]);
+    assert_eq!(longer.len(), 2);
+    assert_eq!(longer[1], b"AUTHENTICATE YQ==\r\n".to_vec());
+}
+
+#[test]
+fn test_plain() {
+    let mut authenticator = Authenticator::plain(b"jilles", b"sesame");
+    assert_eq!(authenticator.start().to_bytes().unwrap(), &b"AUTHENTICATE PLAIN\r\n"[..]);
+    assert_eq!(exchange(&mut authenticator, b"AUTHENTICATE +\r\n"), vec![
+        b"AUTHENTICATE amlsbGVzAGppbGxlcwBzZXNhbWU=\r\n".to_vec(),
+    ]);
+    assert!(exchange(&mut authenticator, b":irc.example.com 900 jilles jilles!jilles@localhost.stack.nl jilles :You are now logged in as jilles\r\n").is_empty());
+    assert!(exchange(&mut authenticator, b":irc.example.com 903 jilles :SASL authentication successful\r\n").is_empty());
+    assert_eq!(authenticator.state(), State::Succeeded);
+    assert_eq!(authenticator.account(), Some(&b"jilles"[..]));
+}
+
+#[test]
+fn test_external() {
+    let mut authenticator = Authenticator::external();
+    assert_eq!(authenticator.start().to_bytes().unwrap(), &b"AUTHENTICATE EXTERNAL\r\n"[..]);
+    assert_eq!(exchange(&mut authenticator, b"AUTHENTICATE +\r\n"), vec![b"AUTHENTICATE +\r\n".to_vec()]);
+    assert!(exchange(&mut authenticator, b":irc.example.com 904 nick :SASL authentication failed\r\n").is_empty());
+    assert_eq!(authenticator.state(), State::Failed(Failure::Rejected));
+
+    let mut authenticator = Authenticator::external();
+    authenticator.start();
+    assert!(exchange(&mut authenticator, b":irc.example.com 908 nick PLAIN,SCRAM-SHA-256 :are available SASL mechanisms\r\n").is_empty());
+    assert!(exchange(&mut authenticator, b":irc.example.com 904 nick :SASL authentication failed\r\n").is_empty());
+    assert_eq!(authenticator.mechanisms(), &[b"PLAIN".to_vec(), b"SCRAM-SHA-256".to_vec()]);
+}
+
+#[test]
+fn test_chunked_challenge() {
+    let mut authenticator = Authenticator::plain(b"nick", b"password");
+    authenticator.start();
+    let mut line = b"AUTHENTICATE ".to_vec();
+    line.extend_from_slice(&[b'A'; CHUNK_LEN]);
+    line.extend_from_slice(b"\r\n");
+    assert!(exchange(&mut authenticator, &line).is_empty());
+    assert_eq!(exchange(&mut authenticator, b"AUTHENTICATE +\r\n").len(), 1);
+
+    // A challenge that never ends is cut off.
+    let mut authenticator = Authenticator::plain(b"nick", b"password");
+    authenticator.start();
+    for _ in 0..MAX_CHALLENGE_LEN / CHUNK_LEN {
+        assert!(exchange(&mut authenticator, &line).is_empty());
+    }
+    assert_eq!(exchange(&mut authenticator, &line), vec![b"AUTHENTICATE *\r\n".to_vec()]);
+    assert_eq!(authenticator.state(), State::Failed(Failure::InvalidChallenge));
+    assert!(exchange(&mut authenticator, &line).is_empty());
+
+    let mut authenticator = Authenticator::plain(b"nick", b"password");
+    authenticator.start();
+    assert_eq!(exchange(&mut authenticator, b"AUTHENTICATE not-base64\r\n"), vec![b"AUTHENTICATE *\r\n".to_vec()]);
+    assert!(exchange(&mut authenticator, b":irc.example.com 906 nick :SASL authentication aborted\r\n").is_empty());
+    assert_eq!(authenticator.state(), State::Failed(Failure::InvalidChallenge));
+}
+
+#[test]
+fn test_scram_sha256() {
+    // Example exchange from RFC 7677.
+    let mut authenticator = Authenticator::scram_sha256(b"user", b"pencil", b"rOprNGfwEbeRWgbNEkqO");
+    assert_eq!(authenticator.start().to_bytes().unwrap(), &b"AUTHENTICATE SCRAM-SHA-256\r\n"[..]);
+    let b64 = |data: &[u8]| {
+        let mut line = b"AUTHENTICATE ".to_vec();
+        line.extend(STANDARD.encode(data).into_bytes());
+        line.extend_from_slice(b"\r\n");
+        line
+    };
+
+    assert_eq!(exchange(&mut authenticator, b"AUTHENTICATE +\r\n"), vec![b64(b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO")]);
+    assert_eq!(
+        exchange(&mut authenticator, &b64(b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096")),
+        vec![b64(b"c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=")],
+    );
+    let mut wrong = authenticator.clone();
+    assert_eq!(
+        exchange(&mut authenticator, &b64(b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")),
+        vec![b"AUTHENTICATE +\r\n".to_vec()],
+    );
+    assert!(exchange(&mut authenticator, b":irc.example.com 903 user :SASL authentication successful\r\n").is_empty());
+    assert_eq!(authenticator.state(), State::Succeeded);
+
+    // A server that doesn't know the password can't produce the signature.
+    assert_eq!(
+        exchange(&mut wrong, &b64(b"v=AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=")),
+        vec![b"AUTHENTICATE *\r\n".to_vec()],
+    );
+    assert_eq!(wrong.state(), State::Failed(Failure::InvalidChallenge));
+
+    // Excessive iteration counts and nonces without a server part are rejected.
+    for challenge in &[
+        &b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=100001"[..],
+        b"r=rOprNGfwEbeRWgbNEkqO,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+    ] {
+        let mut authenticator = Authenticator::scram_sha256(b"user", b"pencil", b"rOprNGfwEbeRWgbNEkqO");
+        authenticator.start();
+        exchange(&mut authenticator, b"AUTHENTICATE +\r\n");
+        assert_eq!(exchange(&mut authenticator, &b64(challenge)), vec![b"AUTHENTICATE *\r\n".to_vec()]);
+        assert_eq!(authenticator.state(), State::Failed(Failure::InvalidChallenge));
+    }
+}
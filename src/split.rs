@@ -0,0 +1,206 @@
+//! Splitting of long `PRIVMSG` and `NOTICE` texts into messages that fit within the
+//! [line length limits](https://modern.ircdocs.horse/#message-format).
+
+use std::io;
+
+use formatting::format_code_len;
+use {escape_value, invalid_input, KnownCommand, OwnedCommand, OwnedMessage, OwnedPrefix};
+
+pub use {MAX_LINE_LEN, MAX_TAGS_LEN};
+
+/// Maximum length of the tags a client may send, excluding the leading `@` and the trailing
+/// space.
+pub const MAX_CLIENT_TAGS_LEN: usize = 4094;
+
+/// Length of the tags in wire format, excluding the leading `@` and the trailing space.
+fn tags_len(tags: &[(Vec<u8>, Option<Vec<u8>>)]) -> usize {
+    let len = tags.iter()
+        .map(|(key, value)| key.len() + value.as_ref().map_or(0, |value| 1 + escape_value(value).len()))
+        .sum::<usize>();
+    len + tags.len().saturating_sub(1)
+}
+
+/// Length of the character or formatting code at the start of `text`. Invalid UTF-8 is treated
+/// as one byte per character.
+fn unit_len(text: &[u8]) -> usize {
+    let len = format_code_len(text);
+    if len > 0 {
+        return len;
+    }
+    let len = match text[0] {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    };
+    if text.len() >= len && text[1..len].iter().all(|&b| b & 0xC0 == 0x80) {
+        len
+    } else {
+        1
+    }
+}
+
+/// Split a line into pieces of at most `max` bytes. Breaks at the last space that fits and
+/// drops it, otherwise at the last character or formatting code that fits. A single character
+/// or formatting code longer than `max` gets a piece of its own.
+fn split_line(mut line: &[u8], max: usize, pieces: &mut Vec<Vec<u8>>) {
+    while line.len() > max {
+        let mut end = 0;
+        let mut space = None;
+        while end < line.len() {
+            let len = unit_len(&line[end..]);
+            if end + len > max {
+                break;
+            }
+            if line[end] == b' ' && end > 0 {
+                space = Some(end);
+            }
+            end += len;
+        }
+        if end < line.len() && line[end] == b' ' {
+            space = Some(end);
+        }
+
+        let (piece, rest) = match space {
+            Some(space) => (&line[..space], &line[space + 1..]),
+            None if end == 0 => line.split_at(unit_len(line)),
+            None => line.split_at(end),
+        };
+        pieces.push(piece.to_vec());
+        line = rest;
+    }
+    if !line.is_empty() {
+        pieces.push(line.to_vec());
+    }
+}
+
+/// Split `text` into `command` messages to `target` that fit within the line length limits.
+///
+/// `source` is the client's own `nick!user@host`, which the server adds before relaying the
+/// message. If it isn't known, use a value of the maximum length. Each piece of text breaks at a
+/// word boundary where possible and never inside a UTF-8 character or a formatting code. Line
+/// breaks in `text` (CRLF, LF or a lone CR) always start a new message, empty lines are skipped
+/// and NUL bytes are dropped. Each message gets all of `tags`.
+///
+/// Fails with `ErrorKind::InvalidInput` if the tags are longer than `MAX_CLIENT_TAGS_LEN` or the
+/// target and source leave no room for the text.
+pub fn split(
+    command: KnownCommand,
+    tags: &[(Vec<u8>, Option<Vec<u8>>)],
+    source: &[u8],
+    target: &[u8],
+    text: &[u8],
+) -> io::Result<Vec<OwnedMessage>> {
+    if tags_len(tags) > MAX_CLIENT_TAGS_LEN {
+        return Err(invalid_input("tags are too long"));
+    }
+
+    // `:source COMMAND target :text\r\n`
+    let overhead = 1 + source.len() + 1 + command.as_bytes().len() + 1 + target.len() + 2 + 2;
+    let max = match MAX_LINE_LEN.checked_sub(overhead) {
+        Some(max) if max > 0 => max,
+        _ => return Err(invalid_input("no room for the text")),
+    };
+
+    let mut pieces = vec![];
+    for line in text.split(|&b| b == b'\r' || b == b'\n') {
+        let line = line.iter().cloned().filter(|&b| b != b'\0').collect::<Vec<_>>();
+        split_line(&line, max, &mut pieces);
+    }
+
+    Ok(pieces.into_iter()
+        .map(|piece| OwnedMessage {
+            tags: tags.to_vec(),
+            prefix: OwnedPrefix::Implicit,
+            command: OwnedCommand::Command(command),
+            params: vec![target.to_vec(), piece],
+        })
+        .collect())
+}
+
+/// Split `text` into `PRIVMSG` messages. See `split`.
+pub fn privmsg(source: &[u8], target: &[u8], text: &[u8]) -> io::Result<Vec<OwnedMessage>> {
+    split(KnownCommand::PRIVMSG, &[], source, target, text)
+}
+
+/// Split `text` into `NOTICE` messages. See `split`.
+pub fn notice(source: &[u8], target: &[u8], text: &[u8]) -> io::Result<Vec<OwnedMessage>> {
+    split(KnownCommand::NOTICE, &[], source, target, text)
+}
+
+#[cfg(test)]
+const SOURCE: &[u8] = b"nick!user@host.example.com";
+
+/// Length of the message as relayed by the server, excluding tags.
+#[cfg(test)]
+fn relayed_len(msg: &OwnedMessage) -> usize {
+    let bytes = msg.to_bytes().unwrap();
+    let start = if bytes[0] == b'@' { bytes.iter().position(|&b| b == b' ').unwrap() + 1 } else { 0 };
+    1 + SOURCE.len() + 1 + bytes.len() - start
+}
+
+#[test]
+fn test_split_words() {
+    let msgs = privmsg(SOURCE, b"#channel", b"short").unwrap();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].to_bytes().unwrap(), &b"PRIVMSG #channel short\r\n"[..]);
+
+    let text = (0..200).map(|i| format!("word{}", i)).collect::<Vec<_>>().join(" ");
+    let msgs = privmsg(SOURCE, b"#channel", text.as_bytes()).unwrap();
+    assert_eq!(msgs.len(), 4);
+    for (i, msg) in msgs.iter().enumerate() {
+        assert!(relayed_len(msg) <= MAX_LINE_LEN);
+        assert!(i == msgs.len() - 1 || relayed_len(msg) > MAX_LINE_LEN - 10);
+        assert!(!msg.params[1].starts_with(b" ") && !msg.params[1].ends_with(b" "));
+    }
+    let joined = msgs.iter().map(|msg| String::from_utf8(msg.params[1].clone()).unwrap()).collect::<Vec<_>>().join(" ");
+    assert_eq!(joined, text);
+
+    let msgs = notice(SOURCE, b"nick", b"first line\r\nsecond line\n\nthird").unwrap();
+    assert_eq!(msgs.iter().map(|msg| &msg.params[1][..]).collect::<Vec<_>>(), vec![&b"first line"[..], b"second line", b"third"]);
+    assert_eq!(msgs[0].command, OwnedCommand::Command(KnownCommand::NOTICE));
+
+    // Every message can be serialized even if the text contains a lone CR or NUL.
+    let msgs = privmsg(SOURCE, b"#channel", b"a\rb\0c\r").unwrap();
+    assert_eq!(msgs.iter().map(|msg| msg.to_bytes().unwrap()).collect::<Vec<_>>(), vec![
+        b"PRIVMSG #channel a\r\n".to_vec(),
+        b"PRIVMSG #channel bc\r\n".to_vec(),
+    ]);
+}
+
+#[test]
+fn test_split_units() {
+    let max = MAX_LINE_LEN - (1 + SOURCE.len() + 1 + 7 + 1 + 8 + 4);
+
+    // Multi-byte characters are kept whole.
+    let text = "ä".repeat(max);
+    let msgs = privmsg(SOURCE, b"#channel", text.as_bytes()).unwrap();
+    assert_eq!(msgs.len(), 2);
+    for msg in &msgs {
+        assert!(::std::str::from_utf8(&msg.params[1]).is_ok());
+        assert!(relayed_len(msg) <= MAX_LINE_LEN);
+    }
+
+    // So are formatting codes.
+    let mut text = vec![b'a'; max - 3];
+    text.extend_from_slice(b"\x0312,04colored");
+    let msgs = privmsg(SOURCE, b"#channel", &text).unwrap();
+    assert_eq!(msgs[0].params[1], vec![b'a'; max - 3]);
+    assert_eq!(msgs[1].params[1], b"\x0312,04colored".to_vec());
+}
+
+#[test]
+fn test_split_errors() {
+    let tags = vec![(b"+draft/reply".to_vec(), Some(vec![b'a'; MAX_CLIENT_TAGS_LEN]))];
+    assert_eq!(split(KnownCommand::PRIVMSG, &tags, SOURCE, b"#channel", b"text").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(privmsg(SOURCE, &[b'#'; MAX_LINE_LEN], b"text").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    // Tags don't count against the line length.
+    let tags = vec![(b"+draft/reply".to_vec(), Some(b"abc".to_vec()))];
+    let text = vec![b'a'; 1000];
+    let msgs = split(KnownCommand::PRIVMSG, &tags, SOURCE, b"#channel", &text).unwrap();
+    assert_eq!(msgs.len(), 3);
+    assert!(msgs.iter().all(|msg| msg.tags == tags && relayed_len(msg) <= MAX_LINE_LEN));
+    // Without a space the text doesn't need the `:` that the length limit is reserved for.
+    assert_eq!(relayed_len(&msgs[0]), MAX_LINE_LEN - 1);
+}
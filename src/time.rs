@@ -0,0 +1,170 @@
+//! Timestamps used by the [`server-time`](https://ircv3.net/specs/extensions/server-time) tag.
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Point in time with millisecond precision.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    millis: i64,
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Parse exactly `len` ASCII digits at the start of `input`.
+fn digits(input: &[u8], len: usize) -> Option<(i64, &[u8])> {
+    if input.len() < len || !input[..len].iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let value = input[..len].iter().fold(0, |value, &b| value * 10 + i64::from(b - b'0'));
+    Some((value, &input[len..]))
+}
+
+/// Consume `separator` at the start of `input`.
+fn separator<'a>(input: &'a [u8], separator: &[u8]) -> Option<&'a [u8]> {
+    match input.first() {
+        Some(b) if separator.contains(b) => Some(&input[1..]),
+        _ => None,
+    }
+}
+
+impl Timestamp {
+    /// Create a timestamp from milliseconds since the Unix epoch.
+    pub fn from_unix_millis(millis: i64) -> Timestamp {
+        Timestamp { millis }
+    }
+
+    /// Milliseconds since the Unix epoch.
+    pub fn unix_millis(&self) -> i64 {
+        self.millis
+    }
+
+    /// Parse an [RFC 3339](https://tools.ietf.org/html/rfc3339) timestamp such as
+    /// `2011-10-19T16:40:51.620Z`. Fractional seconds beyond milliseconds are truncated.
+    pub fn parse_rfc3339(input: &[u8]) -> Option<Timestamp> {
+        let (year, input) = digits(input, 4)?;
+        let (month, input) = digits(separator(input, b"-")?, 2)?;
+        let (day, input) = digits(separator(input, b"-")?, 2)?;
+        let (hour, input) = digits(separator(input, b"Tt ")?, 2)?;
+        let (minute, input) = digits(separator(input, b":")?, 2)?;
+        let (second, mut input) = digits(separator(input, b":")?, 2)?;
+        if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+
+        let mut millis = 0;
+        if let Some(fraction) = separator(input, b".") {
+            let len = fraction.iter().take_while(|b| b.is_ascii_digit()).count();
+            if len == 0 {
+                return None;
+            }
+            millis = fraction[..len].iter().chain(b"00").take(3).fold(0, |millis, &b| millis * 10 + i64::from(b - b'0'));
+            input = &fraction[len..];
+        }
+
+        let offset = match input.first() {
+            Some(&b'Z') | Some(&b'z') if input.len() == 1 => 0,
+            Some(&sign) if sign == b'+' || sign == b'-' => {
+                let (offset_hour, rest) = digits(&input[1..], 2)?;
+                let (offset_minute, rest) = digits(separator(rest, b":")?, 2)?;
+                if !rest.is_empty() || offset_hour > 23 || offset_minute > 59 {
+                    return None;
+                }
+                let offset = offset_hour * 60 + offset_minute;
+                if sign == b'-' { -offset } else { offset }
+            }
+            _ => return None,
+        };
+
+        let days = days_from_civil(year, month, day);
+        let seconds = ((days * 24 + hour) * 60 + minute - offset) * 60 + second;
+        Some(Timestamp { millis: seconds * 1000 + millis })
+    }
+
+    /// Format the timestamp like `server-time` does, e.g. `2011-10-19T16:40:51.620Z`.
+    pub fn to_rfc3339(&self) -> String {
+        self.to_string()
+    }
+
+    /// Convert to `SystemTime`.
+    pub fn to_system_time(&self) -> SystemTime {
+        if self.millis >= 0 {
+            UNIX_EPOCH + Duration::from_millis(self.millis as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_millis(self.millis.unsigned_abs())
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let days = self.millis.div_euclid(86_400_000);
+        let millis = self.millis.rem_euclid(86_400_000);
+        let (year, month, day) = civil_from_days(days);
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year,
+            month,
+            day,
+            millis / 3_600_000,
+            millis / 60_000 % 60,
+            millis / 1000 % 60,
+            millis % 1000,
+        )
+    }
+}
+
+#[test]
+fn test_parse_rfc3339() {
+    let timestamp = Timestamp::parse_rfc3339(b"2011-10-19T16:40:51.620Z").unwrap();
+    assert_eq!(timestamp.unix_millis(), 1_319_042_451_620);
+    assert_eq!(timestamp.to_rfc3339(), "2011-10-19T16:40:51.620Z");
+    assert_eq!(Timestamp::parse_rfc3339(b"1970-01-01T00:00:00Z"), Some(Timestamp::from_unix_millis(0)));
+    assert_eq!(Timestamp::parse_rfc3339(b"1970-01-01T02:00:00.5+02:00"), Some(Timestamp::from_unix_millis(500)));
+    assert_eq!(Timestamp::parse_rfc3339(b"1969-12-31T23:59:59.999123Z"), Some(Timestamp::from_unix_millis(-1)));
+    assert_eq!(Timestamp::from_unix_millis(-1).to_string(), "1969-12-31T23:59:59.999Z");
+    assert_eq!(Timestamp::parse_rfc3339(b"2020-02-29T12:00:00Z").unwrap().to_string(), "2020-02-29T12:00:00.000Z");
+
+    assert_eq!(Timestamp::parse_rfc3339(b"2019-02-29T12:00:00Z"), None);
+    assert_eq!(Timestamp::parse_rfc3339(b"2011-10-19T16:40:51"), None);
+    assert_eq!(Timestamp::parse_rfc3339(b"2011-10-19T16:40:51.Z"), None);
+    assert_eq!(Timestamp::parse_rfc3339(b"2011-10-19T24:00:00Z"), None);
+    assert_eq!(Timestamp::parse_rfc3339(b"2011-10-19T16:40:51Zjunk"), None);
+    assert_eq!(Timestamp::parse_rfc3339(b"+011-10-19T16:40:51Z"), None);
+}
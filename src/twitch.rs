@@ -0,0 +1,550 @@
+//! Decoding of [Twitch IRC tags](https://dev.twitch.tv/docs/irc/tags/) and
+//! [commands](https://dev.twitch.tv/docs/irc/commands/).
+
+use std::str;
+
+use Message;
+
+/// Chat badge, e.g. `subscriber/12`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Badge<'a> {
+    /// Badge name.
+    pub name: &'a [u8],
+    /// Badge version.
+    pub version: &'a [u8],
+}
+
+/// Emote used in a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Emote<'a> {
+    /// Emote ID.
+    pub id: &'a [u8],
+    /// Inclusive `(start, end)` character indices into the message text.
+    pub ranges: Vec<(usize, usize)>,
+}
+
+/// RGB color.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Color {
+    /// Red.
+    pub r: u8,
+    /// Green.
+    pub g: u8,
+    /// Blue.
+    pub b: u8,
+}
+
+/// User's type.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UserType {
+    /// Regular user.
+    Normal,
+    /// Channel moderator.
+    Mod,
+    /// Global moderator.
+    GlobalMod,
+    /// Twitch administrator.
+    Admin,
+    /// Twitch employee.
+    Staff,
+}
+
+fn parse_number<T: str::FromStr>(value: &[u8]) -> Option<T> {
+    str::from_utf8(value).ok().and_then(|value| value.parse().ok())
+}
+
+/// Parse the `badges` tag. Returns `None` if the value is malformed.
+pub fn parse_badges(value: &[u8]) -> Option<Vec<Badge<'_>>> {
+    if value.is_empty() {
+        return Some(vec![]);
+    }
+
+    value.split(|&b| b == b',')
+        .map(|badge| {
+            let mut parts = badge.splitn(2, |&b| b == b'/');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(version)) if !name.is_empty() => Some(Badge { name, version }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Parse the `emotes` tag. Returns `None` if the value is malformed.
+pub fn parse_emotes(value: &[u8]) -> Option<Vec<Emote<'_>>> {
+    if value.is_empty() {
+        return Some(vec![]);
+    }
+
+    value.split(|&b| b == b'/')
+        .map(|emote| {
+            let mut parts = emote.splitn(2, |&b| b == b':');
+            let (id, ranges) = match (parts.next(), parts.next()) {
+                (Some(id), Some(ranges)) if !id.is_empty() => (id, ranges),
+                _ => return None,
+            };
+            let ranges = ranges.split(|&b| b == b',')
+                .map(|range| {
+                    let mut ends = range.splitn(2, |&b| b == b'-');
+                    match (ends.next().and_then(parse_number), ends.next().and_then(parse_number)) {
+                        (Some(start), Some(end)) if start <= end => Some((start, end)),
+                        _ => None,
+                    }
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Some(Emote { id, ranges })
+        })
+        .collect()
+}
+
+/// Parse the `color` tag. Returns `None` if the user hasn't set a color or the value is malformed.
+pub fn parse_color(value: &[u8]) -> Option<Color> {
+    if value.len() != 7 || value[0] != b'#' || !value[1..].iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let rgb = u32::from_str_radix(str::from_utf8(&value[1..]).ok()?, 16).ok()?;
+    Some(Color {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+    })
+}
+
+/// Parse the `user-type` tag.
+pub fn parse_user_type(value: &[u8]) -> Option<UserType> {
+    match value {
+        b"" => Some(UserType::Normal),
+        b"mod" => Some(UserType::Mod),
+        b"global_mod" => Some(UserType::GlobalMod),
+        b"admin" => Some(UserType::Admin),
+        b"staff" => Some(UserType::Staff),
+        _ => None,
+    }
+}
+
+/// Parse a boolean flag such as `mod` or `subscriber`.
+pub fn parse_flag(value: &[u8]) -> Option<bool> {
+    match value {
+        b"0" => Some(false),
+        b"1" => Some(true),
+        _ => None,
+    }
+}
+
+/// Parse the `followers-only` tag of `ROOMSTATE`. `-1` means followers-only mode is off and is
+/// returned as `Some(None)`, otherwise the value is the minimum follow time in minutes.
+pub fn parse_followers_only(value: &[u8]) -> Option<Option<u64>> {
+    if value == b"-1" {
+        Some(None)
+    } else {
+        parse_number(value).map(Some)
+    }
+}
+
+/// Typed view of the Twitch tags of a message.
+#[derive(Copy, Clone, Debug)]
+pub struct Tags<'m, 'a: 'm> {
+    message: &'m Message<'a>,
+}
+
+impl<'m, 'a> Tags<'m, 'a> {
+    /// Create a view of the tags of `message`.
+    pub fn new(message: &'m Message<'a>) -> Tags<'m, 'a> {
+        Tags { message }
+    }
+
+    /// Raw value of the tag `key`.
+    pub fn raw(&self, key: &[u8]) -> Option<&'m [u8]> {
+        self.message.tag(key)
+    }
+
+    /// `badges` tag.
+    pub fn badges(&self) -> Option<Vec<Badge<'m>>> {
+        self.raw(b"badges").and_then(parse_badges)
+    }
+
+    /// `bits` tag.
+    pub fn bits(&self) -> Option<u64> {
+        self.raw(b"bits").and_then(parse_number)
+    }
+
+    /// `color` tag.
+    pub fn color(&self) -> Option<Color> {
+        self.raw(b"color").and_then(parse_color)
+    }
+
+    /// `display-name` tag.
+    pub fn display_name(&self) -> Option<&'m [u8]> {
+        self.raw(b"display-name")
+    }
+
+    /// `emotes` tag.
+    pub fn emotes(&self) -> Option<Vec<Emote<'m>>> {
+        self.raw(b"emotes").and_then(parse_emotes)
+    }
+
+    /// `emote-sets` tag.
+    pub fn emote_sets(&self) -> Option<Vec<u64>> {
+        self.raw(b"emote-sets").and_then(|value| value.split(|&b| b == b',').map(parse_number).collect())
+    }
+
+    /// `id` tag.
+    pub fn id(&self) -> Option<&'m [u8]> {
+        self.raw(b"id")
+    }
+
+    /// `login` tag.
+    pub fn login(&self) -> Option<&'m [u8]> {
+        self.raw(b"login")
+    }
+
+    /// `mod` tag.
+    pub fn is_mod(&self) -> Option<bool> {
+        self.raw(b"mod").and_then(parse_flag)
+    }
+
+    /// `room-id` tag.
+    pub fn room_id(&self) -> Option<u64> {
+        self.raw(b"room-id").and_then(parse_number)
+    }
+
+    /// `subscriber` tag.
+    pub fn subscriber(&self) -> Option<bool> {
+        self.raw(b"subscriber").and_then(parse_flag)
+    }
+
+    /// `turbo` tag.
+    pub fn turbo(&self) -> Option<bool> {
+        self.raw(b"turbo").and_then(parse_flag)
+    }
+
+    /// `user-id` tag.
+    pub fn user_id(&self) -> Option<u64> {
+        self.raw(b"user-id").and_then(parse_number)
+    }
+
+    /// `user-type` tag.
+    pub fn user_type(&self) -> Option<UserType> {
+        self.raw(b"user-type").and_then(parse_user_type)
+    }
+}
+
+/// Twitch-specific command.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command<'m> {
+    /// Chat was cleared or a user was banned or timed out.
+    ClearChat {
+        /// Channel name.
+        channel: &'m [u8],
+        /// Banned user or `None` if the whole chat was cleared.
+        user: Option<&'m [u8]>,
+        /// Timeout duration in seconds or `None` for a permanent ban.
+        ban_duration: Option<u64>,
+    },
+    /// A single message was deleted.
+    ClearMsg {
+        /// Channel name.
+        channel: &'m [u8],
+        /// Deleted message.
+        message: &'m [u8],
+        /// Login of the user who sent the message.
+        login: Option<&'m [u8]>,
+        /// `id` of the deleted message.
+        target_msg_id: Option<&'m [u8]>,
+    },
+    /// Sent after a successful login.
+    GlobalUserState {
+        /// User's display name.
+        display_name: Option<&'m [u8]>,
+        /// User's name color.
+        color: Option<Color>,
+        /// User's global badges.
+        badges: Option<Vec<Badge<'m>>>,
+        /// Emote sets the user can use.
+        emote_sets: Option<Vec<u64>>,
+        /// User's ID.
+        user_id: Option<u64>,
+    },
+    /// Channel started or stopped hosting.
+    HostTarget {
+        /// Hosting channel.
+        channel: &'m [u8],
+        /// Hosted channel or `None` if hosting stopped.
+        target: Option<&'m [u8]>,
+        /// Number of viewers.
+        viewers: Option<u64>,
+    },
+    /// Server is going to restart.
+    Reconnect,
+    /// Channel settings changed. Only the settings that changed are present, except after
+    /// joining the channel when all of them are.
+    RoomState {
+        /// Channel name.
+        channel: &'m [u8],
+        /// Only emotes are allowed.
+        emote_only: Option<bool>,
+        /// Minimum follow time in minutes to chat, or `Some(None)` if followers-only mode is off.
+        followers_only: Option<Option<u64>>,
+        /// Messages must be unique.
+        r9k: Option<bool>,
+        /// Seconds a user must wait between messages, 0 if slow mode is off.
+        slow: Option<u64>,
+        /// Only subscribers may chat.
+        subs_only: Option<bool>,
+    },
+    /// Subscription, raid or similar event.
+    UserNotice {
+        /// Channel name.
+        channel: &'m [u8],
+        /// Message attached to the event.
+        message: Option<&'m [u8]>,
+        /// Kind of the event, e.g. `sub` or `raid`.
+        msg_id: Option<&'m [u8]>,
+    },
+    /// Sent after joining a channel or sending a message.
+    UserState {
+        /// Channel name.
+        channel: &'m [u8],
+        /// User's display name.
+        display_name: Option<&'m [u8]>,
+        /// User's name color.
+        color: Option<Color>,
+        /// User's badges in the channel.
+        badges: Option<Vec<Badge<'m>>>,
+        /// Emote sets the user can use.
+        emote_sets: Option<Vec<u64>>,
+        /// User is a moderator of the channel.
+        is_mod: Option<bool>,
+        /// User is subscribed to the channel.
+        subscriber: Option<bool>,
+    },
+    /// Private message.
+    Whisper {
+        /// Recipient's login.
+        recipient: &'m [u8],
+        /// Message text.
+        message: &'m [u8],
+    },
+}
+
+impl<'m> Command<'m> {
+    /// Interpret `message` as a Twitch command. Returns `None` if it's not a Twitch command or it
+    /// doesn't have the required parameters. Command names are compared case-insensitively.
+    pub fn from_message<'a>(message: &'m Message<'a>) -> Option<Command<'m>> {
+        let name = match message.command {
+            ::Command::String(name) => name,
+            _ => return None,
+        };
+        let params = &message.params[..];
+        let tags = Tags::new(message);
+
+        // Lenient parsing keeps lowercase commands as they are.
+        match &name.to_ascii_uppercase()[..] {
+            b"CLEARCHAT" => Some(Command::ClearChat {
+                channel: params.first()?,
+                user: params.get(1).cloned(),
+                ban_duration: tags.raw(b"ban-duration").and_then(parse_number),
+            }),
+            b"CLEARMSG" => Some(Command::ClearMsg {
+                channel: params.first()?,
+                message: params.get(1)?,
+                login: tags.login(),
+                target_msg_id: tags.raw(b"target-msg-id"),
+            }),
+            b"GLOBALUSERSTATE" => Some(Command::GlobalUserState {
+                display_name: tags.display_name(),
+                color: tags.color(),
+                badges: tags.badges(),
+                emote_sets: tags.emote_sets(),
+                user_id: tags.user_id(),
+            }),
+            b"HOSTTARGET" => {
+                let mut args = params.get(1)?.splitn(2, |&b| b == b' ');
+                let target = args.next()?;
+                Some(Command::HostTarget {
+                    channel: params.first()?,
+                    target: if target == b"-" { None } else { Some(target) },
+                    viewers: args.next().and_then(parse_number),
+                })
+            }
+            b"RECONNECT" => Some(Command::Reconnect),
+            b"ROOMSTATE" => Some(Command::RoomState {
+                channel: params.first()?,
+                emote_only: tags.raw(b"emote-only").and_then(parse_flag),
+                followers_only: tags.raw(b"followers-only").and_then(parse_followers_only),
+                r9k: tags.raw(b"r9k").and_then(parse_flag),
+                slow: tags.raw(b"slow").and_then(parse_number),
+                subs_only: tags.raw(b"subs-only").and_then(parse_flag),
+            }),
+            b"USERNOTICE" => Some(Command::UserNotice {
+                channel: params.first()?,
+                message: params.get(1).cloned(),
+                msg_id: tags.raw(b"msg-id"),
+            }),
+            b"USERSTATE" => Some(Command::UserState {
+                channel: params.first()?,
+                display_name: tags.display_name(),
+                color: tags.color(),
+                badges: tags.badges(),
+                emote_sets: tags.emote_sets(),
+                is_mod: tags.is_mod(),
+                subscriber: tags.subscriber(),
+            }),
+            b"WHISPER" => Some(Command::Whisper {
+                recipient: params.first()?,
+                message: params.get(1)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_parse_values() {
+    assert_eq!(parse_badges(b"staff/1,bits/1000"), Some(vec![
+        Badge { name: b"staff", version: b"1" },
+        Badge { name: b"bits", version: b"1000" },
+    ]));
+    assert_eq!(parse_badges(b""), Some(vec![]));
+    assert_eq!(parse_badges(b"staff"), None);
+
+    assert_eq!(parse_emotes(b"25:0-4,12-16/1902:6-10"), Some(vec![
+        Emote { id: b"25", ranges: vec![(0, 4), (12, 16)] },
+        Emote { id: b"1902", ranges: vec![(6, 10)] },
+    ]));
+    assert_eq!(parse_emotes(b""), Some(vec![]));
+    assert_eq!(parse_emotes(b"25:4-0"), None);
+    assert_eq!(parse_emotes(b"25"), None);
+
+    assert_eq!(parse_color(b"#0D4200"), Some(Color { r: 0x0D, g: 0x42, b: 0x00 }));
+    assert_eq!(parse_color(b""), None);
+    assert_eq!(parse_color(b"#0D420"), None);
+    assert_eq!(parse_color(b"#+D4200"), None);
+
+    assert_eq!(parse_user_type(b""), Some(UserType::Normal));
+    assert_eq!(parse_user_type(b"global_mod"), Some(UserType::GlobalMod));
+    assert_eq!(parse_flag(b"1"), Some(true));
+    assert_eq!(parse_flag(b"yes"), None);
+    assert_eq!(parse_followers_only(b"-1"), Some(None));
+    assert_eq!(parse_followers_only(b"10"), Some(Some(10)));
+    assert_eq!(parse_followers_only(b"-2"), None);
+}
+
+#[test]
+fn test_tags() {
+    let line = b"@badges=global_mod/1,turbo/1;color=#0D4200;display-name=TWITCH_UserNaME;emotes=25:0-4,12-16/1902:6-10;mod=0;room-id=1337;subscriber=0;turbo=1;user-id=1337;user-type=global_mod :twitch_username!twitch_username@twitch_username.tmi.twitch.tv PRIVMSG #channel :Kappa Keepo Kappa\r\n";
+    let msg = ::parse_message(line).unwrap().unwrap().0;
+    let tags = Tags::new(&msg);
+    assert_eq!(tags.badges().unwrap()[0], Badge { name: b"global_mod", version: b"1" });
+    assert_eq!(tags.color(), Some(Color { r: 0x0D, g: 0x42, b: 0x00 }));
+    assert_eq!(tags.display_name(), Some(&b"TWITCH_UserNaME"[..]));
+    assert_eq!(tags.emotes().unwrap().len(), 2);
+    assert_eq!(tags.is_mod(), Some(false));
+    assert_eq!(tags.room_id(), Some(1337));
+    assert_eq!(tags.subscriber(), Some(false));
+    assert_eq!(tags.turbo(), Some(true));
+    assert_eq!(tags.user_id(), Some(1337));
+    assert_eq!(tags.user_type(), Some(UserType::GlobalMod));
+    assert_eq!(tags.bits(), None);
+
+    let line = b"@color=#0D4200;emote-sets=0,33,50;turbo=0;user-type=admin :tmi.twitch.tv GLOBALUSERSTATE\r\n";
+    let msg = ::parse_message(line).unwrap().unwrap().0;
+    assert_eq!(Tags::new(&msg).emote_sets(), Some(vec![0, 33, 50]));
+}
+
+#[test]
+fn test_commands() {
+    let parse = |line: &[u8]| ::parse_message(line).unwrap().unwrap().0.into_owned();
+
+    let msg = parse(b"@ban-duration=1;ban-reason=Follow\\sthe\\srules :tmi.twitch.tv CLEARCHAT #channel :target_username\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), Some(Command::ClearChat {
+        channel: b"#channel",
+        user: Some(b"target_username"),
+        ban_duration: Some(1),
+    }));
+    let msg = parse(b":tmi.twitch.tv CLEARCHAT #channel\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), Some(Command::ClearChat {
+        channel: b"#channel",
+        user: None,
+        ban_duration: None,
+    }));
+    let msg = parse(b"@login=ronni;target-msg-id=abc-123-def :tmi.twitch.tv CLEARMSG #dallas :HeyGuys\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), Some(Command::ClearMsg {
+        channel: b"#dallas",
+        message: b"HeyGuys",
+        login: Some(b"ronni"),
+        target_msg_id: Some(b"abc-123-def"),
+    }));
+    let msg = parse(b":tmi.twitch.tv HOSTTARGET #hosting_channel :target_channel 99999\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), Some(Command::HostTarget {
+        channel: b"#hosting_channel",
+        target: Some(b"target_channel"),
+        viewers: Some(99999),
+    }));
+    let msg = parse(b":tmi.twitch.tv HOSTTARGET #hosting_channel :- 99999\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), Some(Command::HostTarget {
+        channel: b"#hosting_channel",
+        target: None,
+        viewers: Some(99999),
+    }));
+    let msg = parse(b":tmi.twitch.tv RECONNECT\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), Some(Command::Reconnect));
+    let msg = ::parse_message_with(b":tmi.twitch.tv clearchat #channel\r\n", &::ParserConfig::lenient()).unwrap().unwrap().0;
+    assert_eq!(Command::from_message(&msg), Some(Command::ClearChat {
+        channel: b"#channel",
+        user: None,
+        ban_duration: None,
+    }));
+    let msg = parse(b"@msg-id=resub :tmi.twitch.tv USERNOTICE #channel :Great stream -- keep it up!\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), Some(Command::UserNotice {
+        channel: b"#channel",
+        message: Some(b"Great stream -- keep it up!"),
+        msg_id: Some(b"resub"),
+    }));
+    let msg = parse(b":petsgomoo!petsgomoo@petsgomoo.tmi.twitch.tv WHISPER foo :hello\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), Some(Command::Whisper {
+        recipient: b"foo",
+        message: b"hello",
+    }));
+    let msg = parse(b"@emote-only=0;followers-only=-1;r9k=0;rituals=0;room-id=12345678;slow=0;subs-only=0 :tmi.twitch.tv ROOMSTATE #bar\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), Some(Command::RoomState {
+        channel: b"#bar",
+        emote_only: Some(false),
+        followers_only: Some(None),
+        r9k: Some(false),
+        slow: Some(0),
+        subs_only: Some(false),
+    }));
+    let msg = parse(b"@followers-only=10;room-id=12345678 :tmi.twitch.tv ROOMSTATE #bar\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), Some(Command::RoomState {
+        channel: b"#bar",
+        emote_only: None,
+        followers_only: Some(Some(10)),
+        r9k: None,
+        slow: None,
+        subs_only: None,
+    }));
+    let msg = parse(b"@badge-info=;badges=staff/1;color=#0D4200;display-name=ronni;emote-sets=0,33,50;mod=1;subscriber=0;turbo=0;user-type=staff :tmi.twitch.tv USERSTATE #dallas\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), Some(Command::UserState {
+        channel: b"#dallas",
+        display_name: Some(b"ronni"),
+        color: Some(Color { r: 0x0D, g: 0x42, b: 0x00 }),
+        badges: Some(vec![Badge { name: b"staff", version: b"1" }]),
+        emote_sets: Some(vec![0, 33, 50]),
+        is_mod: Some(true),
+        subscriber: Some(false),
+    }));
+    let msg = parse(b"@badge-info=;badges=;color=;display-name=dallas;emote-sets=0;turbo=0;user-id=12345678;user-type=admin :tmi.twitch.tv GLOBALUSERSTATE\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), Some(Command::GlobalUserState {
+        display_name: Some(b"dallas"),
+        color: None,
+        badges: Some(vec![]),
+        emote_sets: Some(vec![0]),
+        user_id: Some(12345678),
+    }));
+
+    let msg = parse(b":tmi.twitch.tv USERSTATE\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), None);
+    let msg = parse(b"PING :tmi.twitch.tv\r\n");
+    assert_eq!(Command::from_message(&msg.borrow()), None);
+}